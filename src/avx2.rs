@@ -0,0 +1,252 @@
+//! Searching by comparing a 32-byte block at a time, using AVX2.
+//!
+//! `PCMPESTRI` has a high latency, so for small sets a few
+//! comparisons are faster, and they handle twice as many bytes at a
+//! time. A single character or a contiguous range of characters only
+//! needs one or two instructions per block.
+
+use std::arch::x86_64::{
+    __m256i, _mm256_cmpeq_epi8, _mm256_load_si256, _mm256_movemask_epi8, _mm256_or_si256,
+    _mm256_set1_epi8, _mm256_setzero_si256, _mm256_sub_epi8, _mm256_subs_epu8,
+};
+
+use dispatch::{Matches,Search};
+use mask::{self,Matcher};
+
+/// The largest set that is faster to search this way.
+pub const MAX_CHARS: u8 = 4;
+
+/// Each of the needle bytes, repeated across a whole block.
+#[derive(Copy,Clone)]
+pub struct Chars {
+    bytes: [__m256i; MAX_CHARS as usize],
+}
+
+impl Chars {
+    /// Sets with fewer than `MAX_CHARS` bytes repeat the first byte,
+    /// so that every comparison can always be made.
+    #[inline(always)]
+    unsafe fn new(needle: u128, count: u8) -> Chars {
+        debug_assert!((1..=MAX_CHARS).contains(&count));
+
+        let byte = |i: u8| {
+            let i = if i < count { i } else { 0 };
+            _mm256_set1_epi8((needle >> (i * 8)) as u8 as i8)
+        };
+        Chars { bytes: [byte(0), byte(1), byte(2), byte(3)] }
+    }
+}
+
+impl Matcher for Chars {
+    const WIDTH: usize = 32;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm256_load_si256(ptr as *const __m256i);
+
+        let matches = _mm256_or_si256(
+            _mm256_or_si256(_mm256_cmpeq_epi8(block, self.bytes[0]), _mm256_cmpeq_epi8(block, self.bytes[1])),
+            _mm256_or_si256(_mm256_cmpeq_epi8(block, self.bytes[2]), _mm256_cmpeq_epi8(block, self.bytes[3])),
+        );
+        _mm256_movemask_epi8(matches) as u32 as u64
+    }
+}
+
+/// A single byte, repeated across a whole block.
+#[derive(Copy,Clone)]
+pub struct Byte(__m256i);
+
+impl Byte {
+    #[inline(always)]
+    unsafe fn new(byte: u8) -> Byte {
+        Byte(_mm256_set1_epi8(byte as i8))
+    }
+}
+
+impl Matcher for Byte {
+    const WIDTH: usize = 32;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm256_load_si256(ptr as *const __m256i);
+        _mm256_movemask_epi8(_mm256_cmpeq_epi8(block, self.0)) as u32 as u64
+    }
+}
+
+/// An inclusive range of bytes. Subtracting the start of the range
+/// moves the bytes within it to the bottom, and a saturating
+/// subtraction of the width of the range leaves only those as zero.
+#[derive(Copy,Clone)]
+pub struct Range {
+    start: __m256i,
+    width: __m256i,
+}
+
+impl Range {
+    /// The bytes from `start` to `end` inclusive; `start` must not be
+    /// after `end`.
+    #[inline(always)]
+    unsafe fn new(start: u8, end: u8) -> Range {
+        Range {
+            start: _mm256_set1_epi8(start as i8),
+            width: _mm256_set1_epi8((end - start) as i8),
+        }
+    }
+}
+
+impl Matcher for Range {
+    const WIDTH: usize = 32;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm256_load_si256(ptr as *const __m256i);
+        let offset = _mm256_sub_epi8(block, self.start);
+        let beyond = _mm256_subs_epu8(offset, self.width);
+        _mm256_movemask_epi8(_mm256_cmpeq_epi8(beyond, _mm256_setzero_si256())) as u32 as u64
+    }
+}
+
+/// A set prepared for searching, so that the bytes are only repeated
+/// across a block once.
+#[derive(Copy,Clone)]
+pub enum Compiled {
+    Byte(Byte),
+    Range(Range),
+    Chars(Chars),
+}
+
+impl Compiled {
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn byte(byte: u8) -> Compiled {
+        Compiled::Byte(Byte::new(byte))
+    }
+
+    /// The bytes from `start` to `end` inclusive.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2, and `start` must not be after `end`.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn range(start: u8, end: u8) -> Compiled {
+        Compiled::Range(Range::new(start, end))
+    }
+
+    /// The first `count` bytes of `needle`.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2, and `count` must be between 1 and
+    /// `MAX_CHARS`.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn chars(needle: u128, count: u8) -> Compiled {
+        Compiled::Chars(Chars::new(needle, count))
+    }
+
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        match *self {
+            Compiled::Byte(ref byte) => mask::search(byte, search, haystack),
+            Compiled::Range(ref range) => mask::search(range, search, haystack),
+            Compiled::Chars(ref chars) => mask::search(chars, search, haystack),
+        }
+    }
+
+    /// Count the bytes of the haystack in the set.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn count(&self, haystack: &[u8]) -> usize {
+        match *self {
+            Compiled::Byte(ref byte) => mask::count(byte, haystack),
+            Compiled::Range(ref range) => mask::count(range, haystack),
+            Compiled::Chars(ref chars) => mask::count(chars, haystack),
+        }
+    }
+
+    /// The matches in the aligned block that holds byte `idx` of the
+    /// haystack.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support AVX2, and `idx` must be within the
+    /// haystack.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        match *self {
+            Compiled::Byte(ref byte) => mask::block(byte, haystack, idx),
+            Compiled::Range(ref range) => mask::block(range, haystack, idx),
+            Compiled::Chars(ref chars) => mask::block(chars, haystack, idx),
+        }
+    }
+}
+
+/// Count each of up to 16 `bytes` in the haystack separately, adding
+/// each count to the tally in the same position.
+///
+/// ### Safety
+///
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn count_each(bytes: &[u8], haystack: &[u8], tallies: &mut [usize]) {
+    let mut matchers = [Byte(_mm256_setzero_si256()); 16];
+    for (matcher, &b) in matchers.iter_mut().zip(bytes) {
+        *matcher = Byte::new(b);
+    }
+    mask::count_each(&matchers[..bytes.len()], haystack, tallies)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::{Byte,Chars,Compiled,Range};
+    use dispatch::Search;
+    use mask::test::check;
+    use AsciiChars;
+
+    #[test]
+    fn agrees_with_a_predicate() {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        // Enabling AVX2 compiles the matchers as they are when searching
+        #[target_feature(enable = "avx2")]
+        unsafe fn matchers_agree(haystack: &[u8], chars: AsciiChars, a: u8, b: u8) -> bool {
+            let (start, end) = (a.min(b), a.max(b));
+            (chars.is_empty() || check(&Chars::new(chars.needle, chars.count), haystack, |x| chars.contains(x))) &&
+                check(&Byte::new(a), haystack, |x| x == a) &&
+                check(&Range::new(start, end), haystack, |x| start <= x && x <= end)
+        }
+
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>, a: u8, b: u8) -> bool {
+            let chars: AsciiChars = bytes.iter().take(4).map(|&b| b & 0x7F).collect();
+            unsafe { matchers_agree(&haystack, chars, a, b) }
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>, u8, u8) -> bool);
+    }
+
+    #[test]
+    fn finds_each_of_four_characters_in_a_long_haystack() {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        let chars = AsciiChars::from_bytes(b"<>&\"");
+        for &b in b"<>&\"" {
+            for position in 0..100 {
+                let mut haystack = vec![b'a'; 100];
+                haystack[position] = b;
+                unsafe {
+                    assert_eq!(Some(position), Compiled::chars(chars.needle, chars.count).search(Search::Find, &haystack));
+                    assert_eq!(Some(position), Compiled::chars(chars.needle, chars.count).search(Search::Rfind, &haystack));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,656 @@
+//! Searching for any subset of the 256 byte values.
+
+use std::{cmp,fmt};
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+use class::Class;
+#[cfg(target_arch = "x86_64")]
+use dispatch;
+use dispatch::{Matches,Search};
+use error::ParseSetError;
+use fallback;
+use iter::{FindIter,Iter,Split,SplitMut};
+use {AsciiChars,AsciiRanges};
+
+/// Searches a string for a set of bytes. Any number of bytes may be
+/// used, including non-ASCII bytes.
+///
+/// The set is stored as a pair of 16-entry tables indexed by the low
+/// nibble of a byte, where each entry has a bit set for every high
+/// nibble that makes a member of the set. This lets SSSE3 and AVX2
+/// look up a whole block of bytes at once with the `PSHUFB`
+/// instruction.
+///
+/// ```
+/// use jetscii::ByteSet;
+/// let unsafe_in_urls: ByteSet = b" \"<>\\^`{|}".iter().cloned().chain(0x80..=0xFF).collect();
+/// assert_eq!(unsafe_in_urls.find("/search?q=a b"), Some(11));
+/// assert_eq!(unsafe_in_urls.find("/caf\u{e9}"), Some(4));
+/// ```
+#[derive(Copy,Clone,PartialEq,Eq,Hash,Default)]
+pub struct ByteSet {
+    /// Members with a high nibble of 0 through 7, then those with a
+    /// high nibble of 8 through 15.
+    tables: [u8; 32],
+}
+
+impl ByteSet {
+    pub const fn new() -> ByteSet {
+        ByteSet { tables: [0; 32] }
+    }
+
+    /// Builds the set from the bytes. This can be used to create a
+    /// constant item.
+    ///
+    /// ```
+    /// use jetscii::ByteSet;
+    /// const QUOTES: ByteSet = ByteSet::from_bytes(b"'\"`");
+    /// assert_eq!(QUOTES.find("say \"hi\""), Some(4));
+    /// ```
+    pub const fn from_bytes(bytes: &[u8]) -> ByteSet {
+        let mut set = ByteSet::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            set = set.with(bytes[i]);
+            i += 1;
+        }
+        set
+    }
+
+    /// Returns the set with a byte added. Calls can be chained in a
+    /// constant item.
+    pub const fn with(mut self, byte: u8) -> ByteSet {
+        let (index, bit) = position(byte);
+        self.tables[index] |= bit;
+        self
+    }
+
+    /// Add a byte to the set to search for.
+    #[inline]
+    pub fn insert(&mut self, byte: u8) {
+        let (index, bit) = position(byte);
+        self.tables[index] |= bit;
+    }
+
+    /// Whether the byte is in the set.
+    #[inline]
+    pub fn contains(&self, byte: u8) -> bool {
+        let (index, bit) = position(byte);
+        self.tables[index] & bit != 0
+    }
+
+    /// The number of bytes in the set.
+    pub fn len(&self) -> usize {
+        self.tables.iter().map(|t| t.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.iter().all(|&t| t == 0)
+    }
+
+    /// An iterator over the bytes in the set, in ascending order.
+    pub fn iter(&self) -> Iter {
+        Iter::new(Class::from_fn(|b| self.contains(b)))
+    }
+
+    // Each byte has its own bit in the tables, so the set operations
+    // can be applied to the tables directly.
+
+    /// The bytes in either set.
+    pub fn union(&self, other: &ByteSet) -> ByteSet {
+        self.zip_with(other, |a, b| a | b)
+    }
+
+    /// The bytes in both sets.
+    pub fn intersection(&self, other: &ByteSet) -> ByteSet {
+        self.zip_with(other, |a, b| a & b)
+    }
+
+    /// The bytes in this set that are not in `other`.
+    pub fn difference(&self, other: &ByteSet) -> ByteSet {
+        self.zip_with(other, |a, b| a & !b)
+    }
+
+    /// Every byte that is not in the set.
+    pub fn complement(&self) -> ByteSet {
+        self.zip_with(self, |a, _| !a)
+    }
+
+    fn zip_with<F>(&self, other: &ByteSet, f: F) -> ByteSet
+        where F: Fn(u8, u8) -> u8
+    {
+        let mut tables = [0; 32];
+        for ((t, &a), &b) in tables.iter_mut().zip(&self.tables).zip(&other.tables) {
+            *t = f(a, b);
+        }
+        ByteSet { tables }
+    }
+
+    /// The same set with only the ASCII members.
+    #[cfg(feature = "unstable")]
+    pub(crate) fn ascii_only(mut self) -> ByteSet {
+        for entry in &mut self.tables[16..] {
+            *entry = 0;
+        }
+        self
+    }
+
+    /// Find the index of the first byte in the set.
+    ///
+    /// AVX2 or SSSE3 is used when the running CPU supports it;
+    /// otherwise each byte is compared in turn.
+    #[inline]
+    pub fn find<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Find, haystack.as_ref())
+    }
+
+    /// Find the index of the last byte in the set.
+    #[inline]
+    pub fn rfind<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Rfind, haystack.as_ref())
+    }
+
+    /// Find the index of the first byte that is *not* in the set.
+    #[inline]
+    pub fn find_not<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::FindNot, haystack.as_ref())
+    }
+
+    /// Find the index of the last byte that is *not* in the set.
+    #[inline]
+    pub fn rfind_not<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::RfindNot, haystack.as_ref())
+    }
+
+    /// An iterator over the index of each byte in the set, along with
+    /// the byte that was found.
+    ///
+    /// ```
+    /// use jetscii::ByteSet;
+    /// let set = ByteSet::from_bytes(b",\xff");
+    /// let found: Vec<_> = set.find_iter(b"a,b\xff").collect();
+    /// assert_eq!(found, [(1, b','), (3, 0xff)]);
+    /// ```
+    #[inline]
+    pub fn find_iter<'h, H>(&self, haystack: &'h H) -> FindIter<'h, ByteSet>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        FindIter::new(*self, haystack.as_ref())
+    }
+
+    /// Count the bytes in the set.
+    ///
+    /// Rather than finding each one in turn, the matches in each block
+    /// of the haystack are counted all at once.
+    #[inline]
+    pub fn count<H>(&self, haystack: &H) -> usize
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            // Safety: the dispatcher has checked that the CPU supports
+            // the instructions.
+            if dispatch::has_avx2() {
+                return unsafe { simd::count_avx2(self, haystack) };
+            }
+            if dispatch::has_ssse3() {
+                return unsafe { simd::count_ssse3(self, haystack) };
+            }
+        }
+
+        haystack.iter().filter(|&&b| self.contains(b)).count()
+    }
+
+    /// Count each byte in the set separately, returning every byte in
+    /// ascending order along with its tally.
+    ///
+    /// The set may hold all 256 bytes, so rather than comparing each
+    /// block against every one of them, each byte of the haystack is
+    /// tallied in a table.
+    ///
+    /// ```
+    /// use jetscii::ByteSet;
+    /// let set = ByteSet::from_bytes(b",\n");
+    /// assert_eq!(set.count_each("a,b,c\nd,e\n"), [(b'\n', 2), (b',', 3)]);
+    /// ```
+    pub fn count_each<H>(&self, haystack: &H) -> Vec<(u8, usize)>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let bytes: Vec<_> = self.iter().collect();
+        let mut tallies = vec![0; bytes.len()];
+        fallback::count_each(&bytes, haystack.as_ref(), &mut tallies);
+        bytes.into_iter().zip(tallies).collect()
+    }
+
+    /// An iterator over the subslices of `haystack` separated by the
+    /// bytes in the set.
+    #[inline]
+    pub fn split<'h, H>(&self, haystack: &'h H) -> Split<'h, ByteSet>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Split::new(*self, haystack.as_ref())
+    }
+
+    /// An iterator over the mutable subslices of `haystack` separated
+    /// by the bytes in the set.
+    #[inline]
+    pub fn split_mut<'h, H>(&self, haystack: &'h mut H) -> SplitMut<'h, ByteSet>
+        where H: ?Sized + AsMut<[u8]>
+    {
+        SplitMut::new(*self, haystack.as_mut())
+    }
+
+    #[inline]
+    pub(crate) fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            // Safety: the dispatcher has checked that the CPU supports
+            // the instructions.
+            if dispatch::has_avx2() {
+                return unsafe { simd::search_avx2(self, search, haystack) };
+            }
+            if dispatch::has_ssse3() {
+                return unsafe { simd::search_ssse3(self, search, haystack) };
+            }
+        }
+
+        let mut bytes = haystack.iter();
+        match search {
+            Search::Find => bytes.position(|&b| self.contains(b)),
+            Search::Rfind => bytes.rposition(|&b| self.contains(b)),
+            Search::FindNot => bytes.position(|&b| !self.contains(b)),
+            Search::RfindNot => bytes.rposition(|&b| !self.contains(b)),
+        }
+    }
+
+    /// The matches in the block of `haystack` that holds byte `idx`,
+    /// extracted from a single comparison.
+    ///
+    /// ### Panics
+    ///
+    /// If `idx` is not within the haystack.
+    #[inline]
+    pub(crate) fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        // The kernels read the whole aligned block around `idx`, which
+        // is only safe when it holds a byte of the haystack.
+        assert!(idx < haystack.len());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            // Safety: the dispatcher has checked that the CPU supports
+            // the instructions.
+            if dispatch::has_avx2() {
+                return unsafe { simd::block_avx2(self, haystack, idx) };
+            }
+            if dispatch::has_ssse3() {
+                return unsafe { simd::block_ssse3(self, haystack, idx) };
+            }
+        }
+
+        let start = idx & !63;
+        let end = cmp::min(start + 64, haystack.len());
+        let mask = haystack[start..end].iter().enumerate()
+            .filter(|&(_, &b)| self.contains(b))
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+        Matches { start, end, mask }
+    }
+}
+
+/// The table entry and bit that represent a byte.
+#[inline]
+const fn position(byte: u8) -> (usize, u8) {
+    let index = (byte >> 7) as usize * 16 + (byte & 0xF) as usize;
+    let bit = 1 << ((byte >> 4) & 0x7);
+    (index, bit)
+}
+
+impl From<AsciiChars> for ByteSet {
+    fn from(chars: AsciiChars) -> ByteSet {
+        chars.iter().collect()
+    }
+}
+
+impl From<AsciiRanges> for ByteSet {
+    fn from(ranges: AsciiRanges) -> ByteSet {
+        (0..128).filter(|&b| fallback::in_ranges(ranges.needle, ranges.count, b)).collect()
+    }
+}
+
+impl IntoIterator for &ByteSet {
+    type Item = u8;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl Extend<u8> for ByteSet {
+    fn extend<I>(&mut self, bytes: I)
+        where I: IntoIterator<Item = u8>
+    {
+        for byte in bytes {
+            self.insert(byte);
+        }
+    }
+}
+
+impl FromIterator<u8> for ByteSet {
+    fn from_iter<I>(bytes: I) -> ByteSet
+        where I: IntoIterator<Item = u8>
+    {
+        let mut set = ByteSet::new();
+        set.extend(bytes);
+        set
+    }
+}
+
+/// Parses the syntax of a regular expression's character class, such
+/// as `[a-z0-9_\-\t]` or `\x80-\xff`. A negated class includes every
+/// byte that is not listed.
+///
+/// ```
+/// use jetscii::ByteSet;
+/// let non_ascii: ByteSet = r"[^\x00-\x7f]".parse().unwrap();
+/// assert_eq!(non_ascii.find("naïve"), Some(2));
+/// ```
+impl FromStr for ByteSet {
+    type Err = ParseSetError;
+
+    fn from_str(s: &str) -> Result<ByteSet, ParseSetError> {
+        Ok(Class::parse(s, 256)?.iter().collect())
+    }
+}
+
+/// Prints the set in the syntax accepted by `from_str`.
+impl fmt::Display for ByteSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Class::from_fn(|b| self.contains(b)).fmt(f)
+    }
+}
+
+impl fmt::Debug for ByteSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<_> = (0..=255u8).filter(|&b| self.contains(b)).collect();
+        f.debug_struct("ByteSet").field("bytes", &bytes).finish()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    use super::ByteSet;
+    use dispatch::{Matches,Search};
+    use mask::{self,Matcher};
+
+    // For each byte in a block, the low nibble selects an entry from
+    // the table for its half of the byte values. `PSHUFB` zeroes any
+    // lane whose index has the high bit set, so flipping the high bit
+    // selects the other half. The high nibble then selects which bit
+    // of the entry must be set.
+
+    const HIGH_NIBBLE_BITS: [u8; 16] = [
+        0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80,
+        0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80,
+    ];
+
+    pub struct Ssse3 {
+        low_half: __m128i,
+        high_half: __m128i,
+        bits: __m128i,
+    }
+
+    impl Ssse3 {
+        #[inline(always)]
+        pub unsafe fn new(set: &ByteSet) -> Ssse3 {
+            Ssse3 {
+                low_half: _mm_loadu_si128(set.tables.as_ptr() as *const __m128i),
+                high_half: _mm_loadu_si128(set.tables[16..].as_ptr() as *const __m128i),
+                bits: _mm_loadu_si128(HIGH_NIBBLE_BITS.as_ptr() as *const __m128i),
+            }
+        }
+    }
+
+    impl Matcher for Ssse3 {
+        const WIDTH: usize = 16;
+
+        #[inline(always)]
+        unsafe fn mask(&self, ptr: *const u8) -> u64 {
+            let block = _mm_load_si128(ptr as *const __m128i);
+
+            let flipped = _mm_xor_si128(block, _mm_set1_epi8(0x80u8 as i8));
+            let entries = _mm_or_si128(
+                _mm_shuffle_epi8(self.low_half, block),
+                _mm_shuffle_epi8(self.high_half, flipped),
+            );
+
+            let high_nibbles = _mm_and_si128(_mm_srli_epi16(block, 4), _mm_set1_epi8(0xF));
+            let bits = _mm_shuffle_epi8(self.bits, high_nibbles);
+
+            let matches = _mm_cmpeq_epi8(_mm_and_si128(entries, bits), bits);
+            _mm_movemask_epi8(matches) as u16 as u64
+        }
+    }
+
+    pub struct Avx2 {
+        low_half: __m256i,
+        high_half: __m256i,
+        bits: __m256i,
+    }
+
+    impl Avx2 {
+        #[inline(always)]
+        pub unsafe fn new(set: &ByteSet) -> Avx2 {
+            // `VPSHUFB` looks up each 128-bit lane separately, so the
+            // tables are repeated in both lanes.
+            let narrow = Ssse3::new(set);
+            Avx2 {
+                low_half: _mm256_broadcastsi128_si256(narrow.low_half),
+                high_half: _mm256_broadcastsi128_si256(narrow.high_half),
+                bits: _mm256_broadcastsi128_si256(narrow.bits),
+            }
+        }
+    }
+
+    impl Matcher for Avx2 {
+        const WIDTH: usize = 32;
+
+        #[inline(always)]
+        unsafe fn mask(&self, ptr: *const u8) -> u64 {
+            let block = _mm256_load_si256(ptr as *const __m256i);
+
+            let flipped = _mm256_xor_si256(block, _mm256_set1_epi8(0x80u8 as i8));
+            let entries = _mm256_or_si256(
+                _mm256_shuffle_epi8(self.low_half, block),
+                _mm256_shuffle_epi8(self.high_half, flipped),
+            );
+
+            let high_nibbles = _mm256_and_si256(_mm256_srli_epi16(block, 4), _mm256_set1_epi8(0xF));
+            let bits = _mm256_shuffle_epi8(self.bits, high_nibbles);
+
+            let matches = _mm256_cmpeq_epi8(_mm256_and_si256(entries, bits), bits);
+            _mm256_movemask_epi8(matches) as u32 as u64
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn search_ssse3(set: &ByteSet, search: Search, haystack: &[u8]) -> Option<usize> {
+        mask::search(&Ssse3::new(set), search, haystack)
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn count_ssse3(set: &ByteSet, haystack: &[u8]) -> usize {
+        mask::count(&Ssse3::new(set), haystack)
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn block_ssse3(set: &ByteSet, haystack: &[u8], idx: usize) -> Matches {
+        mask::block(&Ssse3::new(set), haystack, idx)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn search_avx2(set: &ByteSet, search: Search, haystack: &[u8]) -> Option<usize> {
+        mask::search(&Avx2::new(set), search, haystack)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn count_avx2(set: &ByteSet, haystack: &[u8]) -> usize {
+        mask::count(&Avx2::new(set), haystack)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn block_avx2(set: &ByteSet, haystack: &[u8], idx: usize) -> Matches {
+        mask::block(&Avx2::new(set), haystack, idx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::ByteSet;
+    #[cfg(target_arch = "x86_64")]
+    use super::simd::{Avx2,Ssse3};
+    #[cfg(target_arch = "x86_64")]
+    use mask::test::check;
+    use {AsciiChars,AsciiRanges};
+
+    #[test]
+    fn contains_only_inserted_bytes() {
+        fn prop(bytes: Vec<u8>) -> bool {
+            let set: ByteSet = bytes.iter().cloned().collect();
+            (0..=255u8).all(|b| set.contains(b) == bytes.contains(&b))
+        }
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        fn prop(bytes: Vec<u8>) -> bool {
+            let set: ByteSet = bytes.iter().cloned().collect();
+            set.to_string().parse() == Ok(set)
+        }
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn negated_class_includes_every_other_byte() {
+        let set: ByteSet = "[^a]".parse().unwrap();
+        assert!((0..=255u8).all(|b| set.contains(b) == (b != b'a')));
+        assert_eq!(set.to_string(), r"[\x00-`b-\xff]");
+    }
+
+    #[test]
+    fn set_algebra_works_on_every_byte() {
+        fn prop(a: Vec<u8>, b: Vec<u8>) -> bool {
+            let (set_a, set_b): (ByteSet, ByteSet) = (a.iter().cloned().collect(), b.iter().cloned().collect());
+            let union = set_a.union(&set_b);
+            let intersection = set_a.intersection(&set_b);
+            let difference = set_a.difference(&set_b);
+            let complement = set_a.complement();
+
+            set_a.iter().eq((0..=255).filter(|b| a.contains(b))) &&
+                set_a.len() == set_a.iter().count() &&
+                (0..=255).all(|x| {
+                    union.contains(x) == (a.contains(&x) || b.contains(&x)) &&
+                        intersection.contains(x) == (a.contains(&x) && b.contains(&x)) &&
+                        difference.contains(x) == (a.contains(&x) && !b.contains(&x)) &&
+                        complement.contains(x) != a.contains(&x)
+                })
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn converts_from_the_ascii_sets() {
+        let digits = AsciiRanges::new().with_range(b'0', b'9');
+        assert_eq!(ByteSet::from(digits), "0-9".parse().unwrap());
+        assert_eq!(ByteSet::from(AsciiChars::from_bytes(b"<>&")), "[<>&]".parse().unwrap());
+        assert!(ByteSet::new().is_empty());
+        assert_eq!(ByteSet::new().complement().len(), 256);
+    }
+
+    #[test]
+    fn works_as_position_does() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>) -> bool {
+            let set: ByteSet = bytes.iter().cloned().collect();
+            let each = set.iter().map(|c| (c, haystack.iter().filter(|&&b| b == c).count()));
+
+            set.find(&haystack) == haystack.iter().position(|b| bytes.contains(b)) &&
+                set.rfind(&haystack) == haystack.iter().rposition(|b| bytes.contains(b)) &&
+                set.find_not(&haystack) == haystack.iter().position(|b| !bytes.contains(b)) &&
+                set.rfind_not(&haystack) == haystack.iter().rposition(|b| !bytes.contains(b)) &&
+                set.find_iter(&haystack).eq(haystack.iter().cloned().enumerate().filter(|&(_, b)| bytes.contains(&b))) &&
+                set.find_iter(&haystack).rev().eq(haystack.iter().cloned().enumerate().rev().filter(|&(_, b)| bytes.contains(&b))) &&
+                set.count(&haystack) == haystack.iter().filter(|b| bytes.contains(b)).count() &&
+                set.count_each(&haystack).into_iter().eq(each) &&
+                set.split(&haystack).eq(haystack.split(|b| bytes.contains(b)))
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn all_kernels_agree() {
+        // Enabling the features compiles the matchers as they are when
+        // searching
+        #[target_feature(enable = "ssse3")]
+        unsafe fn ssse3_agrees(haystack: &[u8], set: &ByteSet) -> bool {
+            check(&Ssse3::new(set), haystack, |b| set.contains(b))
+        }
+
+        #[target_feature(enable = "avx2")]
+        unsafe fn avx2_agrees(haystack: &[u8], set: &ByteSet) -> bool {
+            check(&Avx2::new(set), haystack, |b| set.contains(b))
+        }
+
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>) -> bool {
+            let set: ByteSet = bytes.iter().cloned().collect();
+            (!is_x86_feature_detected!("ssse3") || unsafe { ssse3_agrees(&haystack, &set) }) &&
+                (!is_x86_feature_detected!("avx2") || unsafe { avx2_agrees(&haystack, &set) })
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_pattern_with_ascii_members() {
+        fn prop(s: String, bytes: Vec<u8>) -> bool {
+            let set: ByteSet = bytes.iter().cloned().collect();
+            let is_ascii_member = |c: char| c.is_ascii() && bytes.contains(&(c as u8));
+            s.find(set) == s.find(is_ascii_member) &&
+                s.split(set).eq(s.split(is_ascii_member)) &&
+                s.rsplit(set).eq(s.rsplit(is_ascii_member)) &&
+                s.trim_matches(set) == s.trim_matches(is_ascii_member)
+        }
+        quickcheck(prop as fn(String, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn finds_every_byte_value_at_every_position() {
+        let haystack: Vec<u8> = (0..=255).collect();
+
+        for b in 0..=255u8 {
+            let mut set = ByteSet::new();
+            set.insert(b);
+            for start in 0..64 {
+                let expected = (b as usize).checked_sub(start);
+                assert_eq!(expected, set.find(&haystack[start..]), "byte {} from {}", b, start);
+                assert_eq!(expected, set.rfind(&haystack[start..]), "byte {} from {}", b, start);
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Parsing and printing sets of bytes in the syntax of a regular
+//! expression's character class, such as `[a-z0-9_\-\t]`.
+//!
+//! The surrounding brackets are optional. A leading `^` negates the
+//! class, and ranges are written as `start-end`. Bytes may be written
+//! literally if they are ASCII, or as one of the escapes `\t`, `\n`,
+//! `\r`, `\f`, `\v`, `\0`, `\xHH` or a backslash followed by any ASCII
+//! punctuation or space. A `-` at either end of the class is literal.
+
+use std::{fmt,iter};
+
+use error::ParseSetError;
+
+/// The bytes in a character class.
+///
+/// This is also the canonical form of a set, used to compare sets and
+/// combine them.
+#[derive(Copy,Clone,PartialEq,Eq,Hash,Default)]
+pub struct Class {
+    bits: [u128; 2],
+}
+
+impl Class {
+    pub fn new() -> Class {
+        Class::default()
+    }
+
+    /// Builds the class from every byte that passes the test.
+    pub fn from_fn<F>(f: F) -> Class
+        where F: Fn(u8) -> bool
+    {
+        let mut class = Class::new();
+        for byte in (0..=255).filter(|&b| f(b)) {
+            class.insert(byte);
+        }
+        class
+    }
+
+    pub fn insert(&mut self, byte: u8) {
+        self.bits[byte as usize / 128] |= 1 << (byte % 128);
+    }
+
+    pub fn contains(&self, byte: u8) -> bool {
+        self.bits[byte as usize / 128] & 1 << (byte % 128) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        (self.bits[0].count_ones() + self.bits[1].count_ones()) as usize
+    }
+
+    pub fn union(self, other: Class) -> Class {
+        Class { bits: [self.bits[0] | other.bits[0], self.bits[1] | other.bits[1]] }
+    }
+
+    pub fn intersection(self, other: Class) -> Class {
+        Class { bits: [self.bits[0] & other.bits[0], self.bits[1] & other.bits[1]] }
+    }
+
+    pub fn difference(self, other: Class) -> Class {
+        Class { bits: [self.bits[0] & !other.bits[0], self.bits[1] & !other.bits[1]] }
+    }
+
+    pub fn complement(self) -> Class {
+        Class { bits: [!self.bits[0], !self.bits[1]] }
+    }
+
+    /// The members of the class, in ascending order.
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        (0..=255).filter(move |&b| self.contains(b))
+    }
+
+    /// Each maximal inclusive range of members, in ascending order.
+    pub fn ranges(self) -> impl Iterator<Item = (u8, u8)> {
+        let mut bytes = self.iter().peekable();
+        iter::from_fn(move || {
+            let start = bytes.next()?;
+            let mut end = start;
+            while bytes.peek() == Some(&end.wrapping_add(1)) {
+                end = bytes.next()?;
+            }
+            Some((start, end))
+        })
+    }
+
+    /// Parses a class. A negated class holds every byte less than
+    /// `limit` that is not listed.
+    pub fn parse(s: &str, limit: usize) -> Result<Class, ParseSetError> {
+        let mut parser = Parser { bytes: s.as_bytes(), index: 0 };
+        let bracketed = parser.eat(b'[');
+        let negated = parser.eat(b'^');
+
+        let mut class = Class::new();
+
+        loop {
+            match parser.peek() {
+                None if bracketed => return Err(ParseSetError::UnexpectedEnd),
+                None => break,
+                Some(b']') if bracketed => {
+                    parser.index += 1;
+                    break;
+                }
+                Some(_) => {}
+            }
+
+            let start = parser.atom()?;
+            let end = if parser.peek() == Some(b'-') && parser.continues_range(bracketed) {
+                parser.index += 1;
+                parser.atom()?
+            } else {
+                start
+            };
+
+            if start > end {
+                return Err(ParseSetError::ReversedRange { start, end });
+            }
+            for byte in start..=end {
+                class.insert(byte);
+            }
+        }
+
+        if parser.index != s.len() {
+            return Err(ParseSetError::UnexpectedCharacter { index: parser.index });
+        }
+
+        if negated {
+            class = Class::from_fn(|b| (b as usize) < limit && !class.contains(b));
+        }
+        Ok(class)
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.index).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.index += 1;
+        Some(byte)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.index += 1;
+        }
+        matched
+    }
+
+    /// Whether the `-` at the current position is followed by the end
+    /// of a range, rather than being a literal at the end of the class.
+    fn continues_range(&self, bracketed: bool) -> bool {
+        match self.bytes.get(self.index + 1) {
+            None => false,
+            Some(&b']') => !bracketed,
+            Some(_) => true,
+        }
+    }
+
+    /// A single byte, which may be escaped.
+    fn atom(&mut self) -> Result<u8, ParseSetError> {
+        let index = self.index;
+
+        match self.next() {
+            None => Err(ParseSetError::UnexpectedEnd),
+            Some(b'\\') => self.escape(index),
+            Some(byte) if byte.is_ascii() => Ok(byte),
+            Some(_) => Err(ParseSetError::UnexpectedCharacter { index }),
+        }
+    }
+
+    fn escape(&mut self, index: usize) -> Result<u8, ParseSetError> {
+        match self.next() {
+            None => Err(ParseSetError::UnexpectedEnd),
+            Some(b't') => Ok(b'\t'),
+            Some(b'n') => Ok(b'\n'),
+            Some(b'r') => Ok(b'\r'),
+            Some(b'f') => Ok(0x0c),
+            Some(b'v') => Ok(0x0b),
+            Some(b'0') => Ok(0),
+            Some(b'x') => {
+                let high = self.hex_digit(index)?;
+                let low = self.hex_digit(index)?;
+                Ok(high << 4 | low)
+            }
+            Some(byte) if byte.is_ascii_punctuation() || byte == b' ' => Ok(byte),
+            Some(_) => Err(ParseSetError::InvalidEscape { index }),
+        }
+    }
+
+    fn hex_digit(&mut self, index: usize) -> Result<u8, ParseSetError> {
+        let digit = self.next().ok_or(ParseSetError::UnexpectedEnd)?;
+        (digit as char).to_digit(16)
+            .map(|d| d as u8)
+            .ok_or(ParseSetError::InvalidEscape { index })
+    }
+}
+
+/// Prints the class in brackets, with runs of three or more bytes
+/// written as ranges.
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (start, end) in self.ranges() {
+            match end - start {
+                0 => write_byte(f, start)?,
+                1 => {
+                    write_byte(f, start)?;
+                    write_byte(f, end)?;
+                }
+                _ => {
+                    write_byte(f, start)?;
+                    write!(f, "-")?;
+                    write_byte(f, end)?;
+                }
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Class({})", self)
+    }
+}
+
+fn write_byte(f: &mut fmt::Formatter, byte: u8) -> fmt::Result {
+    match byte {
+        b'\\' | b'[' | b']' | b'^' | b'-' => write!(f, "\\{}", byte as char),
+        b'\t' => write!(f, "\\t"),
+        b'\n' => write!(f, "\\n"),
+        b'\r' => write!(f, "\\r"),
+        b' '..=b'~' => write!(f, "{}", byte as char),
+        _ => write!(f, "\\x{:02x}", byte),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::Class;
+    use error::ParseSetError;
+
+    fn members(s: &str) -> Vec<u8> {
+        Class::parse(s, 256).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn parses_literals_ranges_and_escapes() {
+        assert_eq!(members("[abc]"), b"abc");
+        assert_eq!(members("[a-e]"), b"abcde");
+        assert_eq!(members(r"[a-c0-2_\-\t]"), b"\t-012_abc");
+        assert_eq!(members(r"\x00-\x03"), [0, 1, 2, 3]);
+        assert_eq!(members(r"[\x7f-\x81]"), [0x7f, 0x80, 0x81]);
+        assert_eq!(members(r"[\]\[\\\^]"), b"[\\]^");
+        assert_eq!(members("[]"), b"");
+    }
+
+    #[test]
+    fn dashes_at_either_end_are_literal() {
+        assert_eq!(members("[-a]"), b"-a");
+        assert_eq!(members("[a-]"), b"-a");
+        assert_eq!(members("a-"), b"-a");
+        assert_eq!(members("[+--]"), b"+,-");
+    }
+
+    #[test]
+    fn negation_is_within_the_limit() {
+        let class = Class::parse(r"[^\x00-\x1f]", 128).unwrap();
+        assert_eq!(class.iter().collect::<Vec<_>>(), (0x20..0x80).collect::<Vec<_>>());
+
+        let class = Class::parse("^a", 256).unwrap();
+        assert_eq!(class.iter().count(), 255);
+        assert!(!class.contains(b'a'));
+
+        assert_eq!(members(r"[\^a]"), b"^a");
+        assert_eq!(members("[a^]"), b"^a");
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(Class::parse("[abc", 256), Err(ParseSetError::UnexpectedEnd));
+        assert_eq!(Class::parse(r"[a\", 256), Err(ParseSetError::UnexpectedEnd));
+        assert_eq!(Class::parse(r"[\x4]", 256), Err(ParseSetError::InvalidEscape { index: 1 }));
+        assert_eq!(Class::parse(r"[\q]", 256), Err(ParseSetError::InvalidEscape { index: 1 }));
+        assert_eq!(Class::parse("[z-a]", 256), Err(ParseSetError::ReversedRange { start: b'z', end: b'a' }));
+        assert_eq!(Class::parse("[ab]c", 256), Err(ParseSetError::UnexpectedCharacter { index: 4 }));
+        assert_eq!(Class::parse("[é]", 256), Err(ParseSetError::UnexpectedCharacter { index: 1 }));
+    }
+
+    #[test]
+    fn displays_runs_as_ranges() {
+        let class = Class::parse(r"[\x00-\x1f\x7f\-a-zAB]", 256).unwrap();
+        assert_eq!(class.to_string(), r"[\x00-\x1f\-ABa-z\x7f]");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        fn prop(bytes: Vec<u8>) -> bool {
+            let class = Class::from_fn(|b| bytes.contains(&b));
+            Class::parse(&class.to_string(), 256) == Ok(class)
+        }
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+}
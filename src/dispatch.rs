@@ -0,0 +1,175 @@
+//! Chooses the fastest implementation supported by the running CPU.
+//!
+//! Detecting CPU features is relatively costly, so the detection is
+//! performed once and the result cached for the life of the process.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The available `AsciiChars` search implementations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kernel {
+    /// Compares one byte at a time; available everywhere.
+    Fallback,
+    /// Compares a block against each character with SSE2, which every
+    /// x86-64 CPU supports.
+    Sse2,
+    /// Uses the SSE 4.2 `PCMPESTRI` instruction.
+    Sse42,
+    /// Uses AVX2 comparisons for small sets, and `PCMPESTRI` for
+    /// everything else.
+    Avx2,
+    /// Compares a block against each character with `std::simd`; used
+    /// on other architectures when the `portable-simd` feature is
+    /// enabled.
+    PortableSimd,
+}
+
+/// Which bytes to look for, and from which end of the haystack.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Search {
+    /// The first byte in the set.
+    Find,
+    /// The last byte in the set.
+    Rfind,
+    /// The first byte not in the set.
+    FindNot,
+    /// The last byte not in the set.
+    RfindNot,
+}
+
+/// The matches within one block of a haystack, found with a single
+/// comparison.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Matches {
+    /// The index of the first byte of the block within the haystack.
+    pub start: usize,
+    /// The index just past the last byte of the block.
+    pub end: usize,
+    /// Bit `i` is set if byte `start + i` is in the set.
+    pub mask: u64,
+}
+
+/// Returns the best `AsciiChars` implementation for the running CPU.
+#[inline]
+pub fn kernel() -> Kernel {
+    // Every CPU with AVX2 also has SSE 4.2, but it costs nothing to
+    // check.
+    if has_avx2() && has_sse42() {
+        Kernel::Avx2
+    } else if has_sse42() {
+        Kernel::Sse42
+    } else if cfg!(target_arch = "x86_64") {
+        Kernel::Sse2
+    } else if cfg!(feature = "portable-simd") {
+        Kernel::PortableSimd
+    } else {
+        Kernel::Fallback
+    }
+}
+
+const DETECTED: u8 = 1 << 0;
+const SSE42: u8 = 1 << 1;
+#[cfg(target_arch = "x86_64")]
+const SSSE3: u8 = 1 << 2;
+const AVX2: u8 = 1 << 3;
+
+static FEATURES: AtomicU8 = AtomicU8::new(0);
+
+/// Whether the running CPU supports SSE 4.2.
+#[inline]
+pub fn has_sse42() -> bool {
+    // When the feature is enabled for the entire compilation, there's
+    // no need to check at runtime.
+    cfg!(all(target_arch = "x86_64", target_feature = "sse4.2")) || has(SSE42)
+}
+
+/// Whether the running CPU supports SSSE3.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub fn has_ssse3() -> bool {
+    cfg!(all(target_arch = "x86_64", target_feature = "ssse3")) || has(SSSE3)
+}
+
+/// Whether the running CPU supports AVX2.
+#[inline]
+pub fn has_avx2() -> bool {
+    cfg!(all(target_arch = "x86_64", target_feature = "avx2")) || has(AVX2)
+}
+
+#[inline]
+fn has(feature: u8) -> bool {
+    let mut features = FEATURES.load(Ordering::Relaxed);
+    if features & DETECTED == 0 {
+        features = detect();
+    }
+    features & feature != 0
+}
+
+#[cold]
+fn detect() -> u8 {
+    let features = DETECTED | detect_features();
+    FEATURES.store(features, Ordering::Relaxed);
+    features
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect_features() -> u8 {
+    let mut features = 0;
+    if is_x86_feature_detected!("sse4.2") { features |= SSE42 }
+    if is_x86_feature_detected!("ssse3") { features |= SSSE3 }
+    if is_x86_feature_detected!("avx2") { features |= AVX2 }
+    features
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect_features() -> u8 {
+    0
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::Matches;
+    #[cfg(any(target_arch = "x86_64", feature = "portable-simd"))]
+    use super::Search;
+
+    #[cfg(any(target_arch = "x86_64", feature = "portable-simd"))]
+    pub const SEARCHES: [Search; 4] = [Search::Find, Search::Rfind, Search::FindNot, Search::RfindNot];
+
+    /// The result of `search`, found one byte at a time.
+    #[cfg(target_arch = "x86_64")]
+    pub fn reference<F>(search: Search, haystack: &[u8], f: F) -> Option<usize>
+        where F: Fn(u8) -> bool
+    {
+        match search {
+            Search::Find => haystack.iter().position(|&b| f(b)),
+            Search::Rfind => haystack.iter().rposition(|&b| f(b)),
+            Search::FindNot => haystack.iter().position(|&b| !f(b)),
+            Search::RfindNot => haystack.iter().rposition(|&b| !f(b)),
+        }
+    }
+
+    /// The index of every byte that `f` accepts.
+    pub fn positions<F>(haystack: &[u8], f: F) -> Vec<usize>
+        where F: Fn(u8) -> bool
+    {
+        haystack.iter().enumerate().filter(|&(_, &b)| f(b)).map(|(i, _)| i).collect()
+    }
+
+    /// The matches found by comparing one block of at most `width` bytes
+    /// after another, checking that the blocks cover the haystack
+    /// without overlapping.
+    pub fn by_blocks<F>(haystack: &[u8], width: usize, block: F) -> Vec<usize>
+        where F: Fn(usize) -> Matches
+    {
+        let mut found = Vec::new();
+        let mut idx = 0;
+        while idx < haystack.len() {
+            let matches = block(idx);
+            assert_eq!(matches.start, idx);
+            assert!(idx < matches.end && matches.end - matches.start <= width);
+            found.extend((0..64).filter(|i| matches.mask >> i & 1 == 1).map(|i| idx + i));
+            idx = matches.end;
+        }
+        found
+    }
+}
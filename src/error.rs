@@ -0,0 +1,85 @@
+//! The reasons a set of characters could not be built or parsed.
+
+use std::{error,fmt};
+
+/// Returned when a byte cannot be added to an `AsciiChars`.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum AsciiCharsError {
+    /// The byte is not ASCII.
+    NonAscii { byte: u8 },
+    /// The set already holds the maximum number of characters.
+    CapacityExceeded { max: usize },
+    /// The byte is already in the set.
+    Duplicate,
+}
+
+impl fmt::Display for AsciiCharsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsciiCharsError::NonAscii { byte } => {
+                write!(f, "byte {:#04x} is not ASCII", byte)
+            }
+            AsciiCharsError::CapacityExceeded { max } => {
+                write!(f, "cannot search for more than {} characters", max)
+            }
+            AsciiCharsError::Duplicate => {
+                write!(f, "the character is already in the set")
+            }
+        }
+    }
+}
+
+impl error::Error for AsciiCharsError {}
+
+/// Returned when a set cannot be parsed from the syntax of a
+/// character class.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum ParseSetError {
+    /// The class ended in the middle of an escape or before its
+    /// closing bracket.
+    UnexpectedEnd,
+    /// A character that cannot appear at this byte index, such as a
+    /// non-ASCII character or text after the closing bracket.
+    UnexpectedCharacter { index: usize },
+    /// The escape starting at this byte index is not recognized.
+    InvalidEscape { index: usize },
+    /// A range whose start is after its end.
+    ReversedRange { start: u8, end: u8 },
+    /// The class is valid, but the set cannot hold it.
+    Set(AsciiCharsError),
+}
+
+impl fmt::Display for ParseSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseSetError::UnexpectedEnd => {
+                write!(f, "the character class ended unexpectedly")
+            }
+            ParseSetError::UnexpectedCharacter { index } => {
+                write!(f, "unexpected character at index {}", index)
+            }
+            ParseSetError::InvalidEscape { index } => {
+                write!(f, "invalid escape at index {}", index)
+            }
+            ParseSetError::ReversedRange { start, end } => {
+                write!(f, "the range {:?}-{:?} is reversed", start as char, end as char)
+            }
+            ParseSetError::Set(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ParseSetError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ParseSetError::Set(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<AsciiCharsError> for ParseSetError {
+    fn from(e: AsciiCharsError) -> ParseSetError {
+        ParseSetError::Set(e)
+    }
+}
@@ -0,0 +1,136 @@
+//! Searching one byte at a time, for when no accelerated
+//! implementation is available.
+
+use std::cmp;
+
+use dispatch::{Matches,Search};
+
+/// Find the index of the first byte in `haystack` that is one of the
+/// first `count` bytes of `needle`.
+#[inline]
+pub fn find(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|&b| matches(needle, count, b))
+}
+
+/// Find the index of the last byte in `haystack` that is one of the
+/// first `count` bytes of `needle`.
+#[inline]
+pub fn rfind(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().rposition(|&b| matches(needle, count, b))
+}
+
+/// Find the index of the first byte in `haystack` that lies within one
+/// of the ranges formed by the first `count` bytes of `needle`.
+#[inline]
+pub fn find_in_ranges(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|&b| in_ranges(needle, count, b))
+}
+
+/// Find the index of the last byte in `haystack` that lies within one
+/// of the ranges formed by the first `count` bytes of `needle`.
+#[inline]
+pub fn rfind_in_ranges(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().rposition(|&b| in_ranges(needle, count, b))
+}
+
+/// Find the index of the first byte in `haystack` that is none of the
+/// first `count` bytes of `needle`.
+#[inline]
+pub fn find_not(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|&b| !matches(needle, count, b))
+}
+
+/// Find the index of the last byte in `haystack` that is none of the
+/// first `count` bytes of `needle`.
+#[inline]
+pub fn rfind_not(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().rposition(|&b| !matches(needle, count, b))
+}
+
+/// Search `haystack` for the first `count` bytes of `needle`, as the
+/// accelerated implementations do.
+#[inline]
+pub fn chars(needle: u128, count: u8, search: Search, haystack: &[u8]) -> Option<usize> {
+    match search {
+        Search::Find => find(needle, count, haystack),
+        Search::Rfind => rfind(needle, count, haystack),
+        Search::FindNot => find_not(needle, count, haystack),
+        Search::RfindNot => rfind_not(needle, count, haystack),
+    }
+}
+
+/// Count the bytes in `haystack` that are one of the first `count`
+/// bytes of `needle`.
+#[inline]
+pub fn count(needle: u128, count: u8, haystack: &[u8]) -> usize {
+    haystack.iter().filter(|&&b| matches(needle, count, b)).count()
+}
+
+/// Count each of `bytes` in `haystack` separately, adding each count to
+/// the tally in the same position.
+#[inline]
+pub fn count_each(bytes: &[u8], haystack: &[u8], tallies: &mut [usize]) {
+    let mut table = [0; 256];
+    for &b in haystack {
+        table[b as usize] += 1;
+    }
+    for (&b, tally) in bytes.iter().zip(tallies.iter_mut()) {
+        *tally += table[b as usize];
+    }
+}
+
+/// The matches of the first `count` bytes of `needle` in the 64 bytes
+/// of `haystack` that hold byte `idx`, counting from its start.
+#[inline]
+pub fn block(needle: u128, count: u8, haystack: &[u8], idx: usize) -> Matches {
+    let start = idx & !63;
+    let end = cmp::min(start + 64, haystack.len());
+    let mask = haystack[start..end].iter().enumerate()
+        .filter(|&(_, &b)| matches(needle, count, b))
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    Matches { start, end, mask }
+}
+
+/// Find the index of the first byte in `haystack` that lies outside
+/// all of the ranges formed by the first `count` bytes of `needle`.
+#[inline]
+pub fn find_not_in_ranges(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|&b| !in_ranges(needle, count, b))
+}
+
+/// Find the index of the last byte in `haystack` that lies outside
+/// all of the ranges formed by the first `count` bytes of `needle`.
+#[inline]
+pub fn rfind_not_in_ranges(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().rposition(|&b| !in_ranges(needle, count, b))
+}
+
+/// Find the index of the first occurrence of `needle` in `haystack`.
+/// The needle must not be empty.
+#[inline]
+pub fn find_substring(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Find the index of the last occurrence of `needle` in `haystack`.
+/// The needle must not be empty.
+#[inline]
+pub fn rfind_substring(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
+
+#[inline]
+pub fn matches(needle: u128, count: u8, byte: u8) -> bool {
+    (0..count).any(|i| (needle >> (i * 8)) as u8 == byte)
+}
+
+/// Each pair of bytes is an inclusive range, with the lower bound
+/// first.
+#[inline]
+pub fn in_ranges(needle: u128, count: u8, byte: u8) -> bool {
+    (0..count / 2).any(|i| {
+        let start = (needle >> (i * 16)) as u8;
+        let end = (needle >> (i * 16 + 8)) as u8;
+        start <= byte && byte <= end
+    })
+}
@@ -0,0 +1,337 @@
+//! Searching for the same set many times, preparing it only once.
+
+use std::fmt;
+
+use dispatch::{self,Matches,Search};
+#[cfg(any(target_arch = "x86_64", feature = "portable-simd"))]
+use dispatch::Kernel;
+use fallback;
+use iter::FindIter;
+#[cfg(feature = "portable-simd")]
+use simd;
+#[cfg(target_arch = "x86_64")]
+use {avx2,sse2,sse42};
+use {AsciiChars,Strategy};
+
+/// An `AsciiChars` prepared for searching with the fastest kernel for
+/// the running CPU.
+///
+/// Each search with `AsciiChars` chooses a kernel and repeats the
+/// characters across SIMD registers before looking at the haystack.
+/// That takes no time next to a long haystack, but adds up when
+/// searching millions of short ones. A `Finder` does that work once,
+/// when it is created. Only a `Strategy::Set` searched with SSE2 is
+/// still repeated across registers at the start of each search.
+///
+/// ```
+/// use jetscii::{AsciiChars,Finder};
+/// let finder = Finder::new(AsciiChars::from_bytes(b",\n"));
+/// assert_eq!(finder.find("a,b"), Some(1));
+/// assert_eq!(finder.find_from("a,b,c", 2), Some(3));
+///
+/// let found: Vec<_> = finder.find_iter("a,b\nc").collect();
+/// assert_eq!(found, [(1, b','), (3, b'\n')]);
+/// ```
+#[derive(Copy,Clone)]
+pub struct Finder {
+    chars: AsciiChars,
+    compiled: Compiled,
+}
+
+#[derive(Copy,Clone)]
+enum Compiled {
+    Empty,
+    #[cfg(target_arch = "x86_64")]
+    Avx2(avx2::Compiled),
+    #[cfg(target_arch = "x86_64")]
+    Sse2(sse2::Compiled),
+    #[cfg(target_arch = "x86_64")]
+    Sse42(sse42::Needle),
+    #[cfg(feature = "portable-simd")]
+    PortableSimd(simd::Chars),
+    Fallback,
+}
+
+impl Finder {
+    /// Chooses the kernel from the running CPU and the `strategy` of
+    /// the set, as `AsciiChars::find` describes.
+    // Each search through `AsciiChars` builds a `Finder`. Once this is
+    // inlined, the strategy is known, so only the one variant it needs
+    // is built.
+    #[inline(always)]
+    pub fn new(chars: AsciiChars) -> Finder {
+        let compiled = match (dispatch::kernel(), chars.strategy) {
+            (_, Strategy::Empty) => Compiled::Empty,
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports AVX2.
+            (Kernel::Avx2, Strategy::Byte(byte)) => Compiled::Avx2(unsafe { avx2::Compiled::byte(byte) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: as above, and the strategy ensures that `start <= end`.
+            (Kernel::Avx2, Strategy::Range { start, end }) => Compiled::Avx2(unsafe { avx2::Compiled::range(start, end) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: as above.
+            (Kernel::Avx2, _) if chars.fits_avx2() => Compiled::Avx2(unsafe { avx2::Compiled::chars(chars.needle, chars.count) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            (Kernel::Sse42 | Kernel::Sse2, Strategy::Byte(byte)) => Compiled::Sse2(unsafe { sse2::Compiled::byte(byte) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: as above, and the strategy ensures that `start <= end`.
+            (Kernel::Sse42 | Kernel::Sse2, Strategy::Range { start, end }) => Compiled::Sse2(unsafe { sse2::Compiled::range(start, end) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            (Kernel::Sse42 | Kernel::Avx2, _) => Compiled::Sse42(unsafe { sse42::Needle::new(chars.needle, chars.count) }),
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            (Kernel::Sse2, _) => Compiled::Sse2(unsafe { sse2::Compiled::chars(chars.needle, chars.count) }),
+            #[cfg(feature = "portable-simd")]
+            (Kernel::PortableSimd, _) => Compiled::PortableSimd(simd::Chars::new(chars.needle, chars.count)),
+            _ => Compiled::Fallback,
+        };
+
+        Finder { chars, compiled }
+    }
+
+    /// The set being searched for.
+    pub fn chars(&self) -> AsciiChars {
+        self.chars
+    }
+
+    /// Find the index of the first character in the set.
+    #[inline]
+    pub fn find<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Find, haystack.as_ref())
+    }
+
+    /// Find the index of the first character in the set at or after
+    /// `start`. The index is from the beginning of the haystack, not
+    /// from `start`.
+    ///
+    /// ### Panics
+    ///
+    /// If `start` is past the end of the haystack.
+    #[inline]
+    pub fn find_from<H>(&self, haystack: &H, start: usize) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let idx = self.search(Search::Find, &haystack.as_ref()[start..])?;
+        Some(start + idx)
+    }
+
+    /// Find the index of the last character in the set.
+    #[inline]
+    pub fn rfind<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Rfind, haystack.as_ref())
+    }
+
+    /// Find the index of the first character that is *not* in the set.
+    #[inline]
+    pub fn find_not<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::FindNot, haystack.as_ref())
+    }
+
+    /// Find the index of the last character that is *not* in the set.
+    #[inline]
+    pub fn rfind_not<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::RfindNot, haystack.as_ref())
+    }
+
+    /// An iterator over the index of each character in the set, along
+    /// with the character that was found.
+    #[inline]
+    pub fn find_iter<'h, H>(&self, haystack: &'h H) -> FindIter<'h>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        FindIter::new(*self, haystack.as_ref())
+    }
+
+    /// Count the characters in the set, comparing a whole block at a
+    /// time.
+    #[inline]
+    pub fn count<H>(&self, haystack: &H) -> usize
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+        let AsciiChars { needle, count, .. } = self.chars;
+
+        match self.compiled {
+            Compiled::Empty => 0,
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only compiled when the CPU supports AVX2.
+            Compiled::Avx2(ref compiled) => unsafe { compiled.count(haystack) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            Compiled::Sse2(ref compiled) => unsafe { compiled.count(haystack) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only chosen when the CPU supports SSE 4.2.
+            Compiled::Sse42(ref needle) => unsafe { needle.count(haystack) },
+            #[cfg(feature = "portable-simd")]
+            Compiled::PortableSimd(ref chars) => chars.count(haystack),
+            Compiled::Fallback => fallback::count(needle, count, haystack),
+        }
+    }
+
+    /// Count each character in the set separately, returning every
+    /// character in ascending order along with its tally.
+    ///
+    /// Each block of the haystack is compared against every character
+    /// in turn, and the matches for each character counted all at once.
+    pub fn count_each<H>(&self, haystack: &H) -> Vec<(u8, usize)>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+        let mut bytes = [0; 16];
+        let mut len = 0;
+        for b in self.chars.iter() {
+            bytes[len] = b;
+            len += 1;
+        }
+        let bytes = &bytes[..len];
+        let mut tallies = [0; 16];
+
+        match dispatch::kernel() {
+            _ if bytes.is_empty() => {}
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports AVX2.
+            Kernel::Avx2 => unsafe { avx2::count_each(bytes, haystack, &mut tallies) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            Kernel::Sse42 | Kernel::Sse2 => unsafe { sse2::count_each(bytes, haystack, &mut tallies) },
+            #[cfg(feature = "portable-simd")]
+            Kernel::PortableSimd => simd::count_each(bytes, haystack, &mut tallies),
+            _ => fallback::count_each(bytes, haystack, &mut tallies),
+        }
+
+        bytes.iter().cloned().zip(tallies.iter().cloned()).collect()
+    }
+
+    #[inline]
+    pub(crate) fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        let AsciiChars { needle, count, .. } = self.chars;
+
+        match self.compiled {
+            Compiled::Empty => match search {
+                Search::Find | Search::Rfind => None,
+                Search::FindNot => if haystack.is_empty() { None } else { Some(0) },
+                Search::RfindNot => haystack.len().checked_sub(1),
+            },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only compiled when the CPU supports AVX2.
+            Compiled::Avx2(ref compiled) => unsafe { compiled.search(search, haystack) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            Compiled::Sse2(ref compiled) => unsafe { compiled.search(search, haystack) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only chosen when the CPU supports SSE 4.2.
+            Compiled::Sse42(ref needle) => unsafe { needle.search(search, haystack) },
+            #[cfg(feature = "portable-simd")]
+            Compiled::PortableSimd(ref chars) => chars.search(search, haystack),
+            Compiled::Fallback => fallback::chars(needle, count, search, haystack),
+        }
+    }
+
+    /// The matches in the block of `haystack` that holds byte `idx`,
+    /// extracted from a single comparison.
+    ///
+    /// ### Panics
+    ///
+    /// If `idx` is not within the haystack.
+    #[inline]
+    pub(crate) fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        // The kernels read the whole aligned block around `idx`, which
+        // is only safe when it holds a byte of the haystack.
+        assert!(idx < haystack.len());
+        let AsciiChars { needle, count, .. } = self.chars;
+
+        match self.compiled {
+            Compiled::Empty => Matches { start: idx, end: idx + 1, mask: 0 },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only compiled when the CPU supports AVX2.
+            Compiled::Avx2(ref compiled) => unsafe { compiled.block(haystack, idx) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: every x86-64 CPU supports SSE2.
+            Compiled::Sse2(ref compiled) => unsafe { compiled.block(haystack, idx) },
+            #[cfg(target_arch = "x86_64")]
+            // Safety: only chosen when the CPU supports SSE 4.2.
+            Compiled::Sse42(ref needle) => unsafe { needle.block(haystack, idx) },
+            #[cfg(feature = "portable-simd")]
+            Compiled::PortableSimd(ref chars) => chars.block(haystack, idx),
+            Compiled::Fallback => fallback::block(needle, count, haystack, idx),
+        }
+    }
+}
+
+impl From<AsciiChars> for Finder {
+    fn from(chars: AsciiChars) -> Finder {
+        Finder::new(chars)
+    }
+}
+
+impl fmt::Debug for Finder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Finder({:?})", self.chars)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::Finder;
+    use AsciiChars;
+
+    #[test]
+    fn agrees_with_ascii_chars() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>) -> bool {
+            let chars: AsciiChars = bytes.iter().take(16).map(|&b| b & 0x7F).collect();
+            let finder = Finder::new(chars);
+
+            finder.find(&haystack) == chars.find(&haystack) &&
+                finder.rfind(&haystack) == chars.rfind(&haystack) &&
+                finder.find_not(&haystack) == chars.find_not(&haystack) &&
+                finder.rfind_not(&haystack) == chars.rfind_not(&haystack) &&
+                finder.find_iter(&haystack).eq(chars.find_iter(&haystack)) &&
+                finder.count(&haystack) == chars.count(&haystack) &&
+                finder.count_each(&haystack) == chars.count_each(&haystack)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn find_from_reports_indices_from_the_start() {
+        let finder = Finder::new(AsciiChars::from_bytes(b","));
+        let haystack = "a,b,c";
+        assert_eq!(finder.find_from(haystack, 0), Some(1));
+        assert_eq!(finder.find_from(haystack, 1), Some(1));
+        assert_eq!(finder.find_from(haystack, 2), Some(3));
+        assert_eq!(finder.find_from(haystack, 4), None);
+        assert_eq!(finder.find_from(haystack, 5), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn find_from_panics_past_the_end() {
+        Finder::new(AsciiChars::from_bytes(b",")).find_from("a,b", 4);
+    }
+
+    #[test]
+    fn find_iter_works_in_both_directions() {
+        let finder = Finder::new(AsciiChars::from_bytes(b"<>"));
+        let found: Vec<_> = finder.find_iter("<a><b>").rev().collect();
+        assert_eq!(found, [(5, b'>'), (3, b'<'), (2, b'>'), (0, b'<')]);
+    }
+
+    #[test]
+    fn debug_shows_the_chars() {
+        let finder = Finder::new(AsciiChars::from_bytes(b"ab"));
+        assert_eq!(format!("{:?}", finder), r#"Finder(AsciiChars(b"ab"))"#);
+    }
+}
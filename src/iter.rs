@@ -0,0 +1,521 @@
+//! Iterators over the matches in a byte slice, and over the members
+//! of a set.
+
+use std::fmt;
+use std::iter::FusedIterator;
+use std::mem;
+use std::ops::Range;
+
+use class::Class;
+use dispatch::{Matches,Search};
+use needle::Needle;
+use {ByteSet,Finder};
+
+/// A set searched for by the iterators, which can find the matches in
+/// a whole block of the haystack with a single comparison.
+pub trait Set: Copy + fmt::Debug {
+    fn search(&self, search: Search, haystack: &[u8]) -> Option<usize>;
+    /// The matches in the block of `haystack` that holds byte `idx`,
+    /// which must be within it.
+    fn block(&self, haystack: &[u8], idx: usize) -> Matches;
+}
+
+impl Set for Finder {
+    #[inline]
+    fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> { Finder::search(self, search, haystack) }
+    #[inline]
+    fn block(&self, haystack: &[u8], idx: usize) -> Matches { Finder::block(self, haystack, idx) }
+}
+
+impl Set for ByteSet {
+    #[inline]
+    fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> { ByteSet::search(self, search, haystack) }
+    #[inline]
+    fn block(&self, haystack: &[u8], idx: usize) -> Matches { ByteSet::block(self, haystack, idx) }
+}
+
+/// An iterator over the index of each character in the set.
+///
+/// The matches in a block of the haystack are found with a single
+/// comparison and returned one by one from the resulting mask, so a
+/// haystack dense with matches is not searched again for each one.
+///
+/// Created by `AsciiChars::find_iter`, `Finder::find_iter` and
+/// `ByteSet::find_iter`.
+#[derive(Debug,Clone)]
+pub struct FindIter<'a, S = Finder> {
+    set: S,
+    haystack: &'a [u8],
+    // The bytes from `start` to `end` have not been compared yet
+    start: usize,
+    end: usize,
+    // Matches already found in the blocks either side, not yet returned
+    front: Matches,
+    back: Matches,
+}
+
+impl<'a, S> FindIter<'a, S>
+    where S: Set
+{
+    pub(crate) fn new(set: S, haystack: &'a [u8]) -> FindIter<'a, S> {
+        let none = Matches { start: 0, end: 0, mask: 0 };
+        FindIter { set, haystack, start: 0, end: haystack.len(), front: none, back: none }
+    }
+
+    /// Compares the block holding byte `idx`, keeping the matches from
+    /// `idx` onwards.
+    #[inline]
+    fn take_front(&mut self, idx: usize) {
+        let block = self.set.block(&self.haystack[self.start..self.end], idx - self.start);
+        let skipped = idx - self.start - block.start;
+        self.front = Matches { start: idx, end: self.start + block.end, mask: block.mask >> skipped };
+        self.start = self.front.end;
+    }
+
+    /// Compares the block holding byte `idx`, keeping the matches up to
+    /// and including `idx`.
+    #[inline]
+    fn take_back(&mut self, idx: usize) {
+        let block = self.set.block(&self.haystack[self.start..self.end], idx - self.start);
+        let start = self.start + block.start;
+        let kept = !0 >> (63 - (idx - start));
+        self.back = Matches { start, end: idx + 1, mask: block.mask & kept };
+        self.end = start;
+    }
+
+    #[inline]
+    fn item(&self, idx: usize) -> (usize, u8) {
+        (idx, self.haystack[idx])
+    }
+}
+
+impl<'a, S> Iterator for FindIter<'a, S>
+    where S: Set
+{
+    type Item = (usize, u8);
+
+    #[inline]
+    fn next(&mut self) -> Option<(usize, u8)> {
+        loop {
+            if let Some(idx) = self.front.pop_first() {
+                return Some(self.item(idx));
+            }
+            if self.start == self.end {
+                let idx = self.back.pop_first()?;
+                return Some(self.item(idx));
+            }
+
+            // Try the next block before searching ahead, as there is
+            // likely to be another match close by.
+            self.take_front(self.start);
+            if self.front.mask == 0 {
+                match self.set.search(Search::Find, &self.haystack[self.start..self.end]) {
+                    Some(idx) => self.take_front(self.start + idx),
+                    None => self.start = self.end,
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S> DoubleEndedIterator for FindIter<'a, S>
+    where S: Set
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<(usize, u8)> {
+        loop {
+            if let Some(idx) = self.back.pop_last() {
+                return Some(self.item(idx));
+            }
+            if self.start == self.end {
+                let idx = self.front.pop_last()?;
+                return Some(self.item(idx));
+            }
+
+            self.take_back(self.end - 1);
+            if self.back.mask == 0 {
+                match self.set.search(Search::Rfind, &self.haystack[self.start..self.end]) {
+                    Some(idx) => self.take_back(self.start + idx),
+                    None => self.end = self.start,
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S> FusedIterator for FindIter<'a, S> where S: Set {}
+
+impl Matches {
+    #[inline]
+    fn pop_first(&mut self) -> Option<usize> {
+        if self.mask == 0 { return None }
+        let i = self.mask.trailing_zeros() as usize;
+        self.mask &= self.mask - 1;
+        Some(self.start + i)
+    }
+
+    #[inline]
+    fn pop_last(&mut self) -> Option<usize> {
+        if self.mask == 0 { return None }
+        let i = 63 - self.mask.leading_zeros() as usize;
+        self.mask &= !(1 << i);
+        Some(self.start + i)
+    }
+}
+
+/// An iterator over the ranges of each maximal run of consecutive
+/// characters in the set.
+///
+/// Created by `AsciiChars::runs` and `AsciiRanges::runs`.
+#[derive(Debug,Clone)]
+pub struct Runs<'a, N> {
+    needle: N,
+    haystack: &'a [u8],
+    start: usize,
+    end: usize,
+}
+
+impl<'a, N> Runs<'a, N>
+    where N: Needle
+{
+    pub(crate) fn new(needle: N, haystack: &'a [u8]) -> Runs<'a, N> {
+        Runs { needle, haystack, start: 0, end: haystack.len() }
+    }
+}
+
+impl<'a, N> Iterator for Runs<'a, N>
+    where N: Needle
+{
+    type Item = Range<usize>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Range<usize>> {
+        let haystack = &self.haystack[self.start..self.end];
+        let run_start = self.needle.find(haystack)?;
+        let run_len = self.needle.find_not(&haystack[run_start..]).unwrap_or(haystack.len() - run_start);
+
+        let run = self.start + run_start..self.start + run_start + run_len;
+        self.start = run.end;
+        Some(run)
+    }
+}
+
+impl<'a, N> DoubleEndedIterator for Runs<'a, N>
+    where N: Needle
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<Range<usize>> {
+        let haystack = &self.haystack[self.start..self.end];
+        let run_end = self.needle.rfind(haystack)? + 1;
+        let run_start = self.needle.rfind_not(&haystack[..run_end]).map_or(0, |idx| idx + 1);
+
+        let run = self.start + run_start..self.start + run_end;
+        self.end = run.start;
+        Some(run)
+    }
+}
+
+impl<'a, N> FusedIterator for Runs<'a, N> where N: Needle {}
+
+/// An iterator over the bytes in a set, in ascending order.
+///
+/// Created by `AsciiChars::iter` and `ByteSet::iter`.
+#[derive(Debug,Clone)]
+pub struct Iter {
+    class: Class,
+    bytes: Range<u16>,
+}
+
+impl Iter {
+    pub(crate) fn new(class: Class) -> Iter {
+        Iter { class, bytes: 0..256 }
+    }
+}
+
+impl Iterator for Iter {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let class = self.class;
+        self.bytes.by_ref().map(|b| b as u8).find(|&b| class.contains(b))
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    #[inline]
+    fn next_back(&mut self) -> Option<u8> {
+        let class = self.class;
+        self.bytes.by_ref().map(|b| b as u8).rfind(|&b| class.contains(b))
+    }
+}
+
+impl FusedIterator for Iter {}
+
+/// An iterator over the subslices separated by characters in the set.
+///
+/// Created by `AsciiChars::split` and `ByteSet::split`.
+#[derive(Debug,Clone)]
+pub struct Split<'a, S = Finder> {
+    set: S,
+    haystack: &'a [u8],
+    finished: bool,
+}
+
+impl<'a, S> Split<'a, S>
+    where S: Set
+{
+    pub(crate) fn new(set: S, haystack: &'a [u8]) -> Split<'a, S> {
+        Split { set, haystack, finished: false }
+    }
+
+    #[inline]
+    fn finish(&mut self) -> Option<&'a [u8]> {
+        if self.finished { return None }
+        self.finished = true;
+        Some(self.haystack)
+    }
+}
+
+impl<'a, S> Iterator for Split<'a, S>
+    where S: Set
+{
+    type Item = &'a [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.finished { return None }
+
+        match self.set.search(Search::Find, self.haystack) {
+            Some(idx) => {
+                let head = &self.haystack[..idx];
+                self.haystack = &self.haystack[idx + 1..];
+                Some(head)
+            }
+            None => self.finish(),
+        }
+    }
+}
+
+impl<'a, S> DoubleEndedIterator for Split<'a, S>
+    where S: Set
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a [u8]> {
+        if self.finished { return None }
+
+        match self.set.search(Search::Rfind, self.haystack) {
+            Some(idx) => {
+                let tail = &self.haystack[idx + 1..];
+                self.haystack = &self.haystack[..idx];
+                Some(tail)
+            }
+            None => self.finish(),
+        }
+    }
+}
+
+impl<'a, S> FusedIterator for Split<'a, S> where S: Set {}
+
+/// An iterator over the mutable subslices separated by characters in
+/// the set.
+///
+/// Created by `AsciiChars::split_mut` and `ByteSet::split_mut`.
+#[derive(Debug)]
+pub struct SplitMut<'a, S = Finder> {
+    set: S,
+    haystack: &'a mut [u8],
+    finished: bool,
+}
+
+impl<'a, S> SplitMut<'a, S>
+    where S: Set
+{
+    pub(crate) fn new(set: S, haystack: &'a mut [u8]) -> SplitMut<'a, S> {
+        SplitMut { set, haystack, finished: false }
+    }
+
+    #[inline]
+    fn finish(&mut self) -> Option<&'a mut [u8]> {
+        if self.finished { return None }
+        self.finished = true;
+        Some(mem::take(&mut self.haystack))
+    }
+}
+
+impl<'a, S> Iterator for SplitMut<'a, S>
+    where S: Set
+{
+    type Item = &'a mut [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a mut [u8]> {
+        if self.finished { return None }
+
+        match self.set.search(Search::Find, self.haystack) {
+            Some(idx) => {
+                let haystack = mem::take(&mut self.haystack);
+                let (head, tail) = haystack.split_at_mut(idx);
+                self.haystack = &mut tail[1..];
+                Some(head)
+            }
+            None => self.finish(),
+        }
+    }
+}
+
+impl<'a, S> DoubleEndedIterator for SplitMut<'a, S>
+    where S: Set
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a mut [u8]> {
+        if self.finished { return None }
+
+        match self.set.search(Search::Rfind, self.haystack) {
+            Some(idx) => {
+                let haystack = mem::take(&mut self.haystack);
+                let (head, tail) = haystack.split_at_mut(idx);
+                self.haystack = head;
+                Some(&mut tail[1..])
+            }
+            None => self.finish(),
+        }
+    }
+}
+
+impl<'a, S> FusedIterator for SplitMut<'a, S> where S: Set {}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use std::collections::VecDeque;
+    use std::iter;
+    use AsciiChars;
+
+    fn comma_and_newline() -> AsciiChars {
+        let mut chars = AsciiChars::new();
+        chars.push(b',');
+        chars.push(b'\n');
+        chars
+    }
+
+    #[test]
+    fn find_iter_works_in_both_directions() {
+        let chars = comma_and_newline();
+        let haystack = b"a,b\nc,,";
+
+        let forward: Vec<_> = chars.find_iter(haystack).collect();
+        assert_eq!(forward, [(1, b','), (3, b'\n'), (5, b','), (6, b',')]);
+
+        let mut backward: Vec<_> = chars.find_iter(haystack).rev().collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        let mut iter = chars.find_iter(haystack);
+        assert_eq!(iter.next(), Some((1, b',')));
+        assert_eq!(iter.next_back(), Some((6, b',')));
+        assert_eq!(iter.next(), Some((3, b'\n')));
+        assert_eq!(iter.next_back(), Some((5, b',')));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn find_iter_agrees_with_enumerate_in_any_order() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>, from_back: Vec<bool>) -> bool {
+            let chars: AsciiChars = bytes.iter().take(16).map(|&b| b & 0x7F).collect();
+            let mut expected: VecDeque<_> = haystack.iter().cloned().enumerate().filter(|&(_, b)| chars.contains(b)).collect();
+
+            // Take from either end as directed, then drain the rest
+            let mut iter = chars.find_iter(&haystack);
+            from_back.into_iter().chain(iter::repeat(false)).take(haystack.len() + 1).all(|back| {
+                if back {
+                    iter.next_back() == expected.pop_back()
+                } else {
+                    iter.next() == expected.pop_front()
+                }
+            })
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>, Vec<bool>) -> bool);
+    }
+
+    #[test]
+    fn find_iter_returns_every_match_in_dense_haystacks() {
+        let chars = comma_and_newline();
+        for offset in 0..32 {
+            let haystack = &b",,a,b\n".repeat(40)[offset..];
+            let expected: Vec<_> = haystack.iter().cloned().enumerate().filter(|&(_, b)| chars.contains(b)).collect();
+            assert_eq!(chars.find_iter(haystack).collect::<Vec<_>>(), expected);
+
+            let mut backward: Vec<_> = chars.find_iter(haystack).rev().collect();
+            backward.reverse();
+            assert_eq!(backward, expected);
+        }
+    }
+
+    #[test]
+    fn runs_are_maximal_in_both_directions() {
+        fn prop(haystack: Vec<u8>) -> bool {
+            let chars = comma_and_newline();
+            let is_member = |b: u8| b == b',' || b == b'\n';
+
+            // Find the runs one byte at a time
+            let mut expected = Vec::new();
+            let mut i = 0;
+            while i < haystack.len() {
+                if is_member(haystack[i]) {
+                    let start = i;
+                    while i < haystack.len() && is_member(haystack[i]) { i += 1 }
+                    expected.push(start..i);
+                } else {
+                    i += 1;
+                }
+            }
+
+            chars.runs(&haystack).eq(expected.iter().cloned()) &&
+                chars.runs(&haystack).rev().eq(expected.iter().rev().cloned())
+        }
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn split_works_as_slice_split_does() {
+        fn prop(haystack: Vec<u8>) -> bool {
+            let chars = comma_and_newline();
+            let expected: Vec<_> = haystack.split(|&b| b == b',' || b == b'\n').collect();
+            let backward: Vec<_> = haystack.rsplit(|&b| b == b',' || b == b'\n').collect();
+
+            chars.split(&haystack).eq(expected.iter().cloned()) &&
+                chars.split(&haystack).rev().eq(backward.iter().cloned())
+        }
+        quickcheck(prop as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn split_mut_allows_modifying_each_part() {
+        let chars = comma_and_newline();
+        let mut haystack = *b"ab,cd\nef";
+
+        for part in chars.split_mut(&mut haystack) {
+            part.reverse();
+        }
+        assert_eq!(&haystack, b"ba,dc\nfe");
+
+        let mut parts = chars.split_mut(&mut haystack);
+        assert_eq!(parts.next_back().map(|p| &*p), Some(&b"fe"[..]));
+        assert_eq!(parts.next().map(|p| &*p), Some(&b"ba"[..]));
+        assert_eq!(parts.next().map(|p| &*p), Some(&b"dc"[..]));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn can_search_mutable_slices() {
+        let chars = comma_and_newline();
+        let mut haystack = vec![b'a', b'b', b','];
+        let haystack: &mut [u8] = &mut haystack;
+        assert_eq!(chars.find(haystack), Some(2));
+        assert_eq!(chars.rfind(haystack), Some(2));
+    }
+}
@@ -1,10 +1,13 @@
-#![feature(asm)]
-#![feature(pattern)]
-#![cfg_attr(test, feature(test))]
+#![cfg_attr(feature = "unstable", feature(pattern))]
+#![cfg_attr(all(test, feature = "unstable"), feature(test))]
+#![cfg_attr(feature = "portable-simd", feature(portable_simd))]
 
 //!
 //! A tiny library to efficiently search strings for ASCII characters.
 //!
+//! Byte slices may be searched as well as strings, as nothing about
+//! the search requires the haystack to be UTF-8.
+//!
 //! ## Example
 //! ```
 //! use jetscii::AsciiChars;
@@ -12,349 +15,1137 @@
 //! search.push(b'-');
 //! search.push(b':');
 //! let part_number = "86-J52:rev1";
-//! let parts: Vec<_> = part_number.split(search.with_fallback(|c| {
-//!     c == b'-' || c == b':'
-//! })).collect();
-//! assert_eq!(&parts, &["86", "J52", "rev1"]);
+//! assert_eq!(search.find(part_number), Some(2));
 //! ```
 //!
 //! For maximum performance, you can create the searcher as a constant
-//! item. Print an existing `AsciiChars` with the debug formatter to
-//! get the appropriate invocation.
+//! item. The bytes are checked when the constant is compiled, so a
+//! non-ASCII byte or a 17th byte is a compile error.
 //!
 //! ```
 //! use jetscii::AsciiChars;
-//! let search = AsciiChars { needle: 0x0000000000002d3a, count: 2 };
+//! const SEARCH: AsciiChars = AsciiChars::from_bytes(b"-:");
 //! let part_number = "86-J52:rev1";
-//! let parts: Vec<_> = part_number.split(search.with_fallback(|c| {
-//!     c == b'-' || c == b':'
-//! })).collect();
+//! assert_eq!(SEARCH.find(part_number), Some(2));
+//! ```
+//!
+//! ## Patterns
+//!
+//! With the `unstable` feature enabled on a nightly compiler,
+//! `AsciiChars` can be used with the standard string methods that
+//! accept a `Pattern`:
+//!
+//! ```
+//! # #[cfg(feature = "unstable")] {
+//! use jetscii::AsciiChars;
+//! let mut search = AsciiChars::new();
+//! search.push(b'-');
+//! search.push(b':');
+//! let part_number = "86-J52:rev1";
+//! let parts: Vec<_> = part_number.split(search).collect();
 //! assert_eq!(&parts, &["86", "J52", "rev1"]);
+//! # }
 //! ```
-
-use std::fmt;
-use std::str::pattern::{Pattern,Searcher,SearchStep};
-
-/// Searches a string for a set of ASCII characters. Up to 8
+//!
+//! When the optimized instructions are not available, the searcher
+//! falls back to a lookup table derived from the same set of
+//! characters. See `AsciiChars::with_fallback` to supply your own
+//! fallback instead. Enabling the `verify` feature checks each search
+//! against the fallback and panics if they disagree.
+
+use std::{ascii,fmt};
+use std::convert::TryFrom;
+use std::hash::{Hash,Hasher};
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+use class::Class;
+use dispatch::Search;
+
+pub use byteset::ByteSet;
+pub use error::{AsciiCharsError,ParseSetError};
+pub use finder::Finder;
+pub use iter::{FindIter,Iter,Runs,Split,SplitMut};
+#[cfg(feature = "unstable")]
+pub use pattern::{AsciiCharsSearcher,AsciiCharsWithFallback,AsciiRangesSearcher,AsciiRangesWithFallback};
+#[cfg(feature = "unstable")]
+pub use pattern::{ByteSetSearcher,Fallback,FallbackSearcher,LookupTable,Not,NotSearcher,WithFallback};
+#[cfg(feature = "unstable")]
+pub use pattern::SubstringSearcher;
+pub use ranges::AsciiRanges;
+pub use strategy::Strategy;
+pub use substring::Substring;
+
+#[cfg(target_arch = "x86_64")]
+mod avx2;
+mod byteset;
+mod class;
+mod dispatch;
+mod error;
+mod fallback;
+mod finder;
+mod iter;
+#[cfg(target_arch = "x86_64")]
+mod mask;
+mod needle;
+#[cfg(feature = "unstable")]
+mod pattern;
+mod ranges;
+pub mod sets;
+#[cfg(feature = "portable-simd")]
+mod simd;
+#[cfg(target_arch = "x86_64")]
+mod sse2;
+#[cfg(target_arch = "x86_64")]
+mod sse42;
+mod strategy;
+mod substring;
+
+/// Searches a string for a set of ASCII characters. Up to 16
 /// characters may be used.
-///
-/// The instance variables are public to allow creating a AsciiChars
-/// as a constant item. This is temporary until Rust has better
-/// compile-time function evaluation, so consider this an **unstable**
-/// interface.
 #[derive(Copy,Clone)]
 pub struct AsciiChars {
-    pub needle: u64,
-    pub count: u8,
-}
-
-#[cfg(all(feature = "unstable", target_arch = "x86_64"))]
-enum InitialMatch {
-    Complete(Option<usize>),
-    Incomplete(usize),
+    needle: u128,
+    count: u8,
+    strategy: Strategy,
 }
 
 impl AsciiChars {
-    pub fn new() -> AsciiChars {
-        AsciiChars { needle: 0, count: 0 }
+    pub const fn new() -> AsciiChars {
+        AsciiChars { needle: 0, count: 0, strategy: Strategy::Empty }
+    }
+
+    /// Builds the set from each of the bytes in turn. This can be
+    /// used to create a constant item:
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// const XML_DELIMITERS: AsciiChars = AsciiChars::from_bytes(b"<>&");
+    /// assert_eq!(XML_DELIMITERS.find("a &lt; b"), Some(2));
+    /// ```
+    ///
+    /// ### Panics
+    ///
+    /// As for `with`. In a constant, this is a compile error:
+    ///
+    /// ```compile_fail
+    /// use jetscii::AsciiChars;
+    /// const TOO_MANY: AsciiChars = AsciiChars::from_bytes(b"abcdefghijklmnopq");
+    /// ```
+    pub const fn from_bytes(bytes: &[u8]) -> AsciiChars {
+        let mut chars = AsciiChars::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            chars = chars.with(bytes[i]);
+            i += 1;
+        }
+        chars
+    }
+
+    /// Returns the set with a new ASCII character added. Calls can be
+    /// chained in a constant item:
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// const BLANK: AsciiChars = AsciiChars::new().with(b' ').with(b'\t');
+    /// assert_eq!(BLANK.find("key\tvalue"), Some(3));
+    /// ```
+    ///
+    /// ### Panics
+    ///
+    /// - If you add more than 16 characters.
+    /// - If you add a non-ASCII byte.
+    pub const fn with(self, byte: u8) -> AsciiChars {
+        assert!(byte < 128, "AsciiChars can only search for ASCII bytes");
+        assert!(self.count < 16, "AsciiChars can only search for up to 16 bytes");
+        let needle = self.needle << 8 | byte as u128;
+        let count = self.count + 1;
+        AsciiChars { needle, count, strategy: Strategy::of(needle, count) }
     }
 
     /// Add a new ASCII character to the set to search for.
     ///
     /// ### Panics
     ///
-    /// - If you add more than 8 characters.
+    /// - If you add more than 16 characters.
     /// - If you add a non-ASCII byte.
     pub fn push(&mut self, byte: u8) {
-        assert!(byte < 128);
-        assert!(self.count < 8);
-        self.needle <<= 8;
-        self.needle |= byte as u64;
-        self.count += 1;
-    }
-
-    /// Builds a searcher with a fallback implementation for when the
-    /// optimized version is not available. The fallback should search
-    /// for the **exact** same set of characters.
-    pub fn with_fallback<F>(self, fallback: F) -> AsciiCharsWithFallback<F>
-        where F: Fn(u8) -> bool
+        *self = self.with(byte);
+    }
+
+    /// Add a new ASCII character to the set to search for, returning
+    /// an error instead of panicking. Unlike `push`, adding a
+    /// character that is already in the set is an error.
+    ///
+    /// ```
+    /// use jetscii::{AsciiChars,AsciiCharsError};
+    /// let mut search = AsciiChars::new();
+    /// assert_eq!(search.try_push(b'-'), Ok(()));
+    /// assert_eq!(search.try_push(b'-'), Err(AsciiCharsError::Duplicate));
+    /// assert_eq!(search.try_push(0xff), Err(AsciiCharsError::NonAscii { byte: 0xff }));
+    /// ```
+    pub fn try_push(&mut self, byte: u8) -> Result<(), AsciiCharsError> {
+        if byte >= 128 {
+            return Err(AsciiCharsError::NonAscii { byte });
+        }
+        if fallback::matches(self.needle, self.count, byte) {
+            return Err(AsciiCharsError::Duplicate);
+        }
+        if self.count >= 16 {
+            return Err(AsciiCharsError::CapacityExceeded { max: 16 });
+        }
+        self.push(byte);
+        Ok(())
+    }
+
+    /// Builds the set from each of the bytes in turn, as `try_push`
+    /// does.
+    pub fn try_from_iter<I>(bytes: I) -> Result<AsciiChars, AsciiCharsError>
+        where I: IntoIterator<Item = u8>
     {
-        AsciiCharsWithFallback { inner: self, fallback: fallback }
+        let mut chars = AsciiChars::new();
+        chars.try_extend(bytes)?;
+        Ok(chars)
+    }
+
+    /// Adds each of the bytes in turn, as `try_push` does. If any of
+    /// the bytes cannot be added, the set is left unchanged.
+    pub fn try_extend<I>(&mut self, bytes: I) -> Result<(), AsciiCharsError>
+        where I: IntoIterator<Item = u8>
+    {
+        let mut chars = *self;
+        for byte in bytes {
+            chars.try_push(byte)?;
+        }
+        *self = chars;
+        Ok(())
+    }
+
+    /// Whether the byte is in the set.
+    #[inline]
+    pub fn contains(self, byte: u8) -> bool {
+        fallback::matches(self.needle, self.count, byte)
+    }
+
+    /// The number of distinct characters in the set.
+    pub fn len(self) -> usize {
+        self.class().len()
+    }
+
+    /// Whether the set has no characters.
+    pub fn is_empty(self) -> bool {
+        self.count == 0
+    }
+
+    /// An iterator over the distinct characters in the set, in
+    /// ascending order.
+    pub fn iter(self) -> Iter {
+        Iter::new(self.class())
+    }
+
+    /// The characters in either set. There may be more than 16, so
+    /// the result is a `ByteSet`.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let brackets = AsciiChars::from_bytes(b"()[]{}");
+    /// let quotes = AsciiChars::from_bytes(b"'\"`");
+    /// assert_eq!(brackets.union(quotes).find("f(x)"), Some(1));
+    /// ```
+    pub fn union(self, other: AsciiChars) -> ByteSet {
+        self.class().union(other.class()).iter().collect()
+    }
+
+    /// The characters in both sets.
+    pub fn intersection(self, other: AsciiChars) -> AsciiChars {
+        AsciiChars::from_class(self.class().intersection(other.class()))
+    }
+
+    /// The characters in this set that are not in `other`.
+    pub fn difference(self, other: AsciiChars) -> AsciiChars {
+        AsciiChars::from_class(self.class().difference(other.class()))
+    }
+
+    /// Every byte that is not in the set, including all of the
+    /// non-ASCII bytes, as a `ByteSet`.
+    pub fn complement(self) -> ByteSet {
+        self.class().complement().iter().collect()
+    }
+
+    /// How the set is searched for, chosen from its shape as it was
+    /// built.
+    pub const fn strategy(self) -> Strategy {
+        self.strategy
+    }
+
+    /// Small sets are faster to search with AVX2 than `PCMPESTRI`.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn fits_avx2(self) -> bool {
+        (1..=avx2::MAX_CHARS).contains(&self.count)
+    }
+
+    fn class(self) -> Class {
+        let mut class = Class::new();
+        for i in 0..self.count {
+            class.insert((self.needle >> (i * 8)) as u8);
+        }
+        class
+    }
+
+    /// Only called with a subset of an existing set, so it always
+    /// fits.
+    fn from_class(class: Class) -> AsciiChars {
+        class.iter().collect()
     }
 
     /// Find the index of the first character in the set.
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    ///
+    /// Any haystack that can be viewed as bytes may be searched,
+    /// including `str`, `[u8]` and `Vec<u8>`. Nothing about the
+    /// search depends on the haystack being UTF-8.
+    ///
+    /// The search is chosen from the running CPU and the `strategy`
+    /// of the set. On x86-64, a single character or a contiguous range
+    /// of characters is compared against a whole block at once, 32
+    /// bytes at a time with AVX2 or 16 with SSE2. Other sets of up to
+    /// 4 characters are compared against 32 bytes at a time with AVX2;
+    /// otherwise the `PCMPESTRI` instruction is used when the CPU
+    /// supports SSE 4.2. Failing that, each 16-byte block is compared
+    /// against each character in turn with SSE2. On other
+    /// architectures, each byte is compared in turn, unless the
+    /// `portable-simd` feature is enabled, in which case blocks are
+    /// compared using `std::simd`.
+    ///
+    /// The kernel is chosen, and the set prepared for it, on every
+    /// call. To search many short haystacks for the same set, a
+    /// `Finder` does this only once.
     #[inline]
-    pub fn find(self, haystack: &str) -> Option<usize> {
-        let haystack = haystack.as_bytes();
-        let mut len = haystack.len();
-
-        if len == 0 { return None }
-
-        // The PCMPxSTRx instructions always read 16 bytes worth of
-        // data. To avoid walking off the end of a page (and
-        // potentially into a protected area), we read in 16-byte
-        // chunks aligned to the *end* of the string. The instructions
-        // handle truly unaligned access just fine; the trick lies in
-        // searching the leftover bytes at the beginning of the
-        // string.
-
-        let true_ptr = haystack.as_ptr();
-
-        // Start at the 16-byte-aligned block *before* the string data
-        // starts
-        let ptr = (true_ptr as usize & !0xF) as *const u8;
-
-        // Find where the string really starts
-        let initial_offset = true_ptr as usize & 0xF;
-        let mut offset = initial_offset;
-
-        // If the string is magically aligned, skip this extra work
-        if offset != 0 {
-            match self.search_initial_unaligned_string(ptr, offset, len) {
-                InitialMatch::Complete(result) => return result,
-                InitialMatch::Incomplete(length_of_leading_str) => {
-                    offset = 16;
-                    len -= length_of_leading_str;
+    pub fn find<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Find, haystack.as_ref())
+    }
+
+    /// Find the index of the last character in the set.
+    #[inline]
+    pub fn rfind<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::Rfind, haystack.as_ref())
+    }
+
+    /// Find the index of the first character that is *not* in the set.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let mut whitespace = AsciiChars::new();
+    /// whitespace.push(b' ');
+    /// whitespace.push(b'\t');
+    /// assert_eq!(whitespace.find_not(" \t value"), Some(3));
+    /// ```
+    #[inline]
+    pub fn find_not<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::FindNot, haystack.as_ref())
+    }
+
+    /// Find the index of the last character that is *not* in the set.
+    #[inline]
+    pub fn rfind_not<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        self.search(Search::RfindNot, haystack.as_ref())
+    }
+
+    #[inline]
+    fn search(self, search: Search, haystack: &[u8]) -> Option<usize> {
+        Finder::new(self).search(search, haystack)
+    }
+
+    /// An iterator over the ranges of each maximal run of consecutive
+    /// characters in the set.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let mut digits = AsciiChars::new();
+    /// for d in b'0'..=b'9' { digits.push(d) }
+    /// let runs: Vec<_> = digits.runs("tel: 555-0199").collect();
+    /// assert_eq!(runs, [5..8, 9..13]);
+    /// ```
+    #[inline]
+    pub fn runs<H>(self, haystack: &H) -> Runs<'_, AsciiChars>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Runs::new(self, haystack.as_ref())
+    }
+
+    /// An iterator over the index of each character in the set, along
+    /// with the character that was found.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let mut search = AsciiChars::new();
+    /// search.push(b',');
+    /// search.push(b'\n');
+    /// let found: Vec<_> = search.find_iter("a,b\nc").collect();
+    /// assert_eq!(found, [(1, b','), (3, b'\n')]);
+    /// ```
+    #[inline]
+    pub fn find_iter<H>(self, haystack: &H) -> FindIter<'_>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        FindIter::new(Finder::new(self), haystack.as_ref())
+    }
+
+    /// Count the characters in the set.
+    ///
+    /// Rather than finding each one in turn, the matches in each block
+    /// of the haystack are counted all at once.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let newline = AsciiChars::from_bytes(b"\n");
+    /// assert_eq!(newline.count("one\ntwo\nthree\n"), 3);
+    /// ```
+    #[inline]
+    pub fn count<H>(self, haystack: &H) -> usize
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Finder::new(self).count(haystack)
+    }
+
+    /// Count each character in the set separately, returning every
+    /// character in ascending order along with its tally.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let delimiters = AsciiChars::from_bytes(b",\n");
+    /// assert_eq!(delimiters.count_each("a,b,c\nd,e\n"), [(b'\n', 2), (b',', 3)]);
+    /// ```
+    pub fn count_each<H>(self, haystack: &H) -> Vec<(u8, usize)>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Finder::new(self).count_each(haystack)
+    }
+
+    /// An iterator over the subslices of `haystack` separated by the
+    /// characters in the set.
+    ///
+    /// ```
+    /// use jetscii::AsciiChars;
+    /// let mut search = AsciiChars::new();
+    /// search.push(b'-');
+    /// search.push(b':');
+    /// let parts: Vec<_> = search.split("86-J52:rev1").collect();
+    /// assert_eq!(parts, [&b"86"[..], b"J52", b"rev1"]);
+    /// ```
+    #[inline]
+    pub fn split<H>(self, haystack: &H) -> Split<'_>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Split::new(Finder::new(self), haystack.as_ref())
+    }
+
+    /// An iterator over the mutable subslices of `haystack` separated
+    /// by the characters in the set.
+    #[inline]
+    pub fn split_mut<H>(self, haystack: &mut H) -> SplitMut<'_>
+        where H: ?Sized + AsMut<[u8]>
+    {
+        SplitMut::new(Finder::new(self), haystack.as_mut())
+    }
+}
+
+impl Default for AsciiChars {
+    fn default() -> AsciiChars {
+        AsciiChars::new()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for AsciiChars {
+    type Error = AsciiCharsError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<AsciiChars, AsciiCharsError> {
+        AsciiChars::try_from_iter(bytes.iter().cloned())
+    }
+}
+
+/// Panics as `push` does; see `AsciiChars::try_extend`.
+impl Extend<u8> for AsciiChars {
+    fn extend<I>(&mut self, bytes: I)
+        where I: IntoIterator<Item = u8>
+    {
+        for byte in bytes {
+            self.push(byte);
+        }
+    }
+}
+
+/// Panics as `push` does; see `AsciiChars::try_from_iter`.
+impl FromIterator<u8> for AsciiChars {
+    fn from_iter<I>(bytes: I) -> AsciiChars
+        where I: IntoIterator<Item = u8>
+    {
+        let mut chars = AsciiChars::new();
+        chars.extend(bytes);
+        chars
+    }
+}
+
+/// Parses the syntax of a regular expression's character class, such
+/// as `[a-z0-9_\-\t]` or `\x00-\x1f`.
+///
+/// ```
+/// use jetscii::AsciiChars;
+/// let delimiters: AsciiChars = r"[\t,;|]".parse().unwrap();
+/// assert_eq!(delimiters.find("a;b"), Some(1));
+/// assert_eq!(delimiters.to_string(), r"[\t,;|]");
+/// ```
+///
+/// The brackets are optional. A class may be negated with `^`, but as
+/// only ASCII characters can be searched for, the negation only
+/// includes ASCII characters.
+impl FromStr for AsciiChars {
+    type Err = ParseSetError;
+
+    fn from_str(s: &str) -> Result<AsciiChars, ParseSetError> {
+        let class = Class::parse(s, 128)?;
+        Ok(AsciiChars::try_from_iter(class.iter())?)
+    }
+}
+
+/// Prints the set in the syntax accepted by `from_str`.
+impl fmt::Display for AsciiChars {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.class().fmt(f)
+    }
+}
+
+/// Sets are equal if they contain the same characters, regardless of
+/// the order they were added in or any duplicates.
+impl PartialEq for AsciiChars {
+    fn eq(&self, other: &AsciiChars) -> bool {
+        self.class() == other.class()
+    }
+}
+
+impl Eq for AsciiChars {}
+
+impl Hash for AsciiChars {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.class().hash(state);
+    }
+}
+
+impl IntoIterator for AsciiChars {
+    type Item = u8;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for AsciiChars {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The first byte pushed is the most significant
+        write!(f, "AsciiChars(b\"")?;
+        for i in (0..self.count).rev() {
+            write!(f, "{}", ascii::escape_default((self.needle >> (i * 8)) as u8))?;
+        }
+        write!(f, "\")")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+    #[cfg(unix)]
+    extern crate libc;
+
+    use super::{AsciiChars,AsciiCharsError,ByteSet,ParseSetError};
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+    use super::fallback;
+    #[cfg(target_arch = "x86_64")]
+    use super::{avx2,sse2,sse42};
+    use dispatch::test::{by_blocks,positions};
+    #[cfg(target_arch = "x86_64")]
+    use dispatch::Search;
+    #[cfg(all(unix, target_arch = "x86_64"))]
+    use dispatch::Matches;
+    use self::quickcheck::{quickcheck,Arbitrary,Gen};
+    #[cfg(feature = "unstable")]
+    use super::{Fallback,LookupTable,Not};
+    #[cfg(feature = "unstable")]
+    use std::str::pattern::{Pattern,ReverseSearcher,Searcher,SearchStep};
+    #[cfg(feature = "unstable")]
+    use std::iter;
+    #[cfg(unix)]
+    use std::{slice,str,ptr};
+    #[cfg(unix)]
+    use super::{Finder,Strategy};
+
+    pub const SPACE: AsciiChars       = AsciiChars::from_bytes(b" ");
+    pub const XML_DELIM_3: AsciiChars = AsciiChars::from_bytes(b"<>&");
+    pub const XML_DELIM_5: AsciiChars = AsciiChars::from_bytes(b"<>&'\"");
+
+    #[derive(Debug,Copy,Clone)]
+    struct AsciiChar(u8);
+
+    impl Arbitrary for AsciiChar {
+        fn arbitrary(g: &mut Gen) -> AsciiChar {
+            AsciiChar(u8::arbitrary(g) & 0x7F)
+        }
+    }
+
+    #[test]
+    fn works_as_find_does_for_single_characters() {
+        fn prop(s: String, c: AsciiChar) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c.0);
+            searcher.find(&s) == s.find(c.0 as char)
+        }
+        quickcheck(prop as fn(String, AsciiChar) -> bool);
+    }
+
+    #[test]
+    fn works_as_find_does_for_multiple_characters() {
+        fn prop(s: String, (c1, c2, c3, c4): (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            searcher.push(c4.0);
+            searcher.find(&s) == s.find(&[c1.0 as char, c2.0 as char, c3.0 as char, c4.0 as char][..])
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    fn works_as_rfind_does_for_multiple_characters() {
+        fn prop(s: String, (c1, c2, c3, c4): (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            searcher.push(c4.0);
+            searcher.rfind(&s) == s.rfind(&[c1.0 as char, c2.0 as char, c3.0 as char, c4.0 as char][..])
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    fn find_not_works_as_position_does() {
+        fn prop(s: String, (c1, c2, c3): (AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            let not_member = |&b: &u8| b != c1.0 && b != c2.0 && b != c3.0;
+            searcher.find_not(&s) == s.bytes().position(|b| not_member(&b)) &&
+                searcher.rfind_not(&s) == s.bytes().rposition(|b| not_member(&b))
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_negated_pattern() {
+        fn prop(s: String, (c1, c2, c3): (AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            let chars = [c1.0 as char, c2.0 as char, c3.0 as char];
+            let not_member = |c: char| !chars.contains(&c);
+            s.find(Not(searcher)) == s.find(not_member) &&
+                s.split(Not(searcher)).eq(s.split(not_member)) &&
+                s.rsplit(Not(searcher)).eq(s.rsplit(not_member)) &&
+                s.trim_matches(Not(searcher)) == s.trim_matches(not_member) &&
+                s.matches(Not(searcher)).eq(s.matches(not_member))
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_find_does_for_multiple_characters_as_pattern() {
+        fn prop(s: String, (c1, c2, c3, c4): (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            searcher.push(c4.0);
+            s.find(searcher.with_fallback(|b| b == c1.0 || b == c2.0 || b == c3.0 || b == c4.0)) == s.find(&[c1.0 as char, c2.0 as char, c3.0 as char, c4.0 as char][..])
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn sse42_agrees_with_fallback() {
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        fn prop(s: String, chars: Vec<AsciiChar>) -> bool {
+            let mut searcher = AsciiChars::new();
+            for c in chars.iter().take(16) {
+                searcher.push(c.0);
+            }
+            let AsciiChars { needle, count, .. } = searcher;
+            let haystack = s.as_bytes();
+            let simd = unsafe { sse42::find::<sse42::EqualAny>(needle, count, haystack) };
+            let simd_rev = unsafe { sse42::rfind::<sse42::EqualAny>(needle, count, haystack) };
+            let simd_not = unsafe { sse42::find::<sse42::NotEqualAny>(needle, count, haystack) };
+            let simd_rev_not = unsafe { sse42::rfind::<sse42::NotEqualAny>(needle, count, haystack) };
+            simd == fallback::find(needle, count, haystack) &&
+                simd_rev == fallback::rfind(needle, count, haystack) &&
+                simd_not == fallback::find_not(needle, count, haystack) &&
+                simd_rev_not == fallback::rfind_not(needle, count, haystack)
+        }
+        quickcheck(prop as fn(String, Vec<AsciiChar>) -> bool);
+    }
+
+    #[test]
+    fn count_works_as_filter_does() {
+        fn prop(haystack: Vec<u8>, chars: Vec<AsciiChar>, offset: u8) -> bool {
+            let chars: AsciiChars = chars.iter().take(16).map(|c| c.0).collect();
+            let haystack = &haystack[(offset as usize % 64).min(haystack.len())..];
+            let each = chars.iter().map(|c| (c, haystack.iter().filter(|&&b| b == c).count()));
+
+            chars.count(haystack) == haystack.iter().filter(|&&b| chars.contains(b)).count() &&
+                chars.count_each(haystack).into_iter().eq(each)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<AsciiChar>, u8) -> bool);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn kernels_count_each_character() {
+        let avx2 = is_x86_feature_detected!("avx2");
+
+        fn counts<F>(bytes: &[u8], f: F) -> Vec<usize>
+            where F: FnOnce(&mut [usize])
+        {
+            let mut tallies = vec![0; bytes.len()];
+            f(&mut tallies);
+            tallies
+        }
+
+        for offset in 0..64 {
+            let haystack = &b"a<b>&c<<".repeat(40)[offset..];
+            let bytes = b"&<>\"'";
+            let expected: Vec<_> = bytes.iter().map(|&c| haystack.iter().filter(|&&b| b == c).count()).collect();
+
+            assert_eq!(counts(bytes, |t| fallback::count_each(bytes, haystack, t)), expected);
+            assert_eq!(counts(bytes, |t| unsafe { sse2::count_each(bytes, haystack, t) }), expected);
+            if avx2 {
+                assert_eq!(counts(bytes, |t| unsafe { avx2::count_each(bytes, haystack, t) }), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn count_each_reports_characters_that_are_absent() {
+        let chars = AsciiChars::from_bytes(b"<>&");
+        assert_eq!(chars.count_each("<a><b>"), [(b'&', 0), (b'<', 2), (b'>', 2)]);
+        assert_eq!(AsciiChars::new().count_each("abc"), []);
+    }
+
+    #[test]
+    fn blocks_and_counts_hold_every_match() {
+        #[cfg(target_arch = "x86_64")]
+        let sse42 = is_x86_feature_detected!("sse4.2");
+
+        for offset in 0..64 {
+            let haystack = &[b"a<b>&c".repeat(30), b"x".repeat(70)].concat()[offset..];
+            let AsciiChars { needle, count, .. } = XML_DELIM_3;
+            let expected = positions(haystack, |b| XML_DELIM_3.contains(b));
+
+            assert_eq!(by_blocks(haystack, 64, |idx| fallback::block(needle, count, haystack, idx)), expected);
+            assert_eq!(fallback::count(needle, count, haystack), expected.len());
+            #[cfg(target_arch = "x86_64")]
+            {
+                if sse42 {
+                    let loaded = unsafe { sse42::Needle::new(needle, count) };
+                    assert_eq!(by_blocks(haystack, 16, |idx| unsafe { loaded.block(haystack, idx) }), expected);
+                    assert_eq!(unsafe { loaded.count(haystack) }, expected.len());
                 }
             }
         }
+    }
 
-        while len != 0 {
-            let res: usize;
+    // Long enough to be searched 64 bytes at a time, starting at every
+    // alignment.
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn kernels_find_every_position_in_long_haystacks() {
+        let sse42 = is_x86_feature_detected!("sse4.2");
+        let avx2 = is_x86_feature_detected!("avx2");
+        let AsciiChars { needle, count, .. } = XML_DELIM_3;
+
+        let mut buffer = vec![b'a'; 264];
+        for offset in 0..64 {
+            for position in 0..200 {
+                buffer[offset + position] = b'&';
+                let haystack = &buffer[offset..offset + 200];
+                let expected = Some(position);
+
+                unsafe {
+                    if sse42 {
+                        assert_eq!(sse42::find::<sse42::EqualAny>(needle, count, haystack), expected);
+                    }
+                    if avx2 {
+                        assert_eq!(avx2::Compiled::chars(needle, count).search(Search::Find, haystack), expected);
+                        assert_eq!(avx2::Compiled::byte(b'a').search(Search::FindNot, haystack), expected);
+                        assert_eq!(avx2::Compiled::range(b'&', b'\'').search(Search::Find, haystack), expected);
+                    }
+                    assert_eq!(sse2::Compiled::chars(needle, count).search(Search::Find, haystack), expected);
+                    assert_eq!(sse2::Compiled::byte(b'a').search(Search::FindNot, haystack), expected);
+                    assert_eq!(sse2::Compiled::range(b'&', b'\'').search(Search::Find, haystack), expected);
+                }
 
-            unsafe {
-                asm!("pcmpestri $$0, ($1, $5), $2"
-                     : // output operands
-                     "={ecx}"(res)
-                     : // input operands
-                     "r"(ptr),
-                     "x"(self.needle),
-                     "{rdx}"(len),
-                     "{rax}"(self.count),
-                     "r"(offset)
-                     : // clobbers
-                     : // options
-                 );
+                buffer[offset + position] = b'a';
             }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_find_does_as_pattern_without_fallback() {
+        fn prop(s: String, (c1, c2, c3): (AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            let chars = [c1.0 as char, c2.0 as char, c3.0 as char];
+            s.find(searcher) == s.find(&chars[..]) &&
+                s.split(searcher).eq(s.split(&chars[..]))
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_double_ended_pattern() {
+        fn prop(s: String, (c1, c2, c3): (AsciiChar, AsciiChar, AsciiChar)) -> bool {
+            let mut searcher = AsciiChars::new();
+            searcher.push(c1.0);
+            searcher.push(c2.0);
+            searcher.push(c3.0);
+            let chars = [c1.0 as char, c2.0 as char, c3.0 as char];
+            s.rfind(searcher) == s.rfind(&chars[..]) &&
+                s.rsplit(searcher).eq(s.rsplit(&chars[..])) &&
+                s.trim_matches(searcher) == s.trim_matches(&chars[..]) &&
+                s.trim_end_matches(searcher) == s.trim_end_matches(&chars[..]) &&
+                s.split(searcher).rev().eq(s.split(&chars[..]).rev())
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    }
 
-            // We know if it matched if the zero flag is set (or
-            // unset?), we shouldn't need to test res...
-            if res == 16 {
-                offset += 16;
-                len = len.saturating_sub(16);
-            } else {
-                return Some(res + offset - initial_offset);
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn next_match_and_next_reject_agree_with_next() {
+        type Ranges = Vec<(usize, usize)>;
+
+        fn steps<'a, S: ReverseSearcher<'a>>(mut searcher: S, reverse: bool) -> (Ranges, Ranges) {
+            let (mut matches, mut rejects) = (Vec::new(), Vec::new());
+            loop {
+                match if reverse { searcher.next_back() } else { searcher.next() } {
+                    SearchStep::Match(a, b) => matches.push((a, b)),
+                    SearchStep::Reject(a, b) => rejects.push((a, b)),
+                    SearchStep::Done => return (matches, rejects),
+                }
             }
         }
 
-        None
+        fn agree<P>(pattern: P, s: &str) -> bool
+            where P: Pattern + Copy,
+                  for<'a> P::Searcher<'a>: ReverseSearcher<'a>
+        {
+            let (matches, rejects) = steps(pattern.into_searcher(s), false);
+            let (matches_back, rejects_back) = steps(pattern.into_searcher(s), true);
+
+            let mut forward = pattern.into_searcher(s);
+            let mut backward = pattern.into_searcher(s);
+            let mut forward_rejects = pattern.into_searcher(s);
+            let mut backward_rejects = pattern.into_searcher(s);
+
+            iter::from_fn(|| forward.next_match()).eq(matches) &&
+                iter::from_fn(|| backward.next_match_back()).eq(matches_back) &&
+                iter::from_fn(|| forward_rejects.next_reject()).eq(rejects) &&
+                iter::from_fn(|| backward_rejects.next_reject_back()).eq(rejects_back)
+        }
+
+        fn prop(s: String, (c1, c2, c3): (AsciiChar, AsciiChar, AsciiChar), other: u8) -> bool {
+            let bytes = [c1.0, c2.0, c3.0];
+            agree(AsciiChars::from_bytes(&bytes), &s) &&
+                agree(ByteSet::from_bytes(&bytes).with(other), &s)
+        }
+        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar), u8) -> bool);
     }
 
-    #[inline]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
-    fn search_initial_unaligned_string(&self, ptr: *const u8, offset: usize, len: usize) -> InitialMatch {
-        // We use the PCMPESTRM instruction on the 16-byte-aligned
-        // block that contains the *start* of the string. This returns
-        // a mask of all the matching characters. We can can then
-        // ignore unrelated leading bits to find the index of the
-        // first related character (if any).
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn next_match_and_next_reject_can_be_interleaved() {
+        let mut searcher = XML_DELIM_3.into_searcher("<<a>b&&c");
+        assert_eq!(searcher.next_reject(), Some((2, 3)));
+        assert_eq!(searcher.next_match(), Some((3, 4)));
+        assert_eq!(searcher.next_match(), Some((5, 6)));
+        assert_eq!(searcher.next_reject(), Some((7, 8)));
+        assert_eq!(searcher.next_match(), None);
+        assert_eq!(searcher.next_reject(), None);
+
+        let mut searcher = XML_DELIM_3.into_searcher("<<a>b&&c");
+        assert_eq!(searcher.next_reject_back(), Some((7, 8)));
+        assert_eq!(searcher.next_match_back(), Some((6, 7)));
+        assert_eq!(searcher.next_reject_back(), Some((4, 5)));
+        assert_eq!(searcher.next_match(), Some((0, 1)));
+        assert_eq!(searcher.next_match_back(), Some((3, 4)));
+        assert_eq!(searcher.next_match(), Some((1, 2)));
+        assert_eq!(searcher.next_reject(), Some((2, 3)));
+        assert_eq!(searcher.next_match(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn lookup_table_matches_only_pushed_bytes() {
+        let table = LookupTable::new(XML_DELIM_3);
+        for b in 0..=255u8 {
+            assert_eq!(b == b'<' || b == b'>' || b == b'&', table.matches(b), "byte {:#04x}", b);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "unstable", feature = "verify"))]
+    #[should_panic(expected = "disagree about byte 0x3e ('>') at index 3")]
+    fn verify_reports_fallback_missing_a_byte() {
+        let parts: Vec<_> = "a<b>c&d".split(XML_DELIM_3.with_fallback(|c| c == b'<' || c == b'&')).collect();
+        drop(parts);
+    }
+
+    #[test]
+    #[cfg(all(feature = "unstable", feature = "verify"))]
+    #[should_panic(expected = "disagree about byte 0x62 ('b') at index 1")]
+    fn verify_reports_fallback_with_an_extra_byte() {
+        let parts: Vec<_> = "abc".split(SPACE.with_fallback(|c| c == b' ' || c == b'b')).collect();
+        drop(parts);
+    }
+
+    #[test]
+    #[cfg(all(feature = "unstable", feature = "verify"))]
+    #[should_panic(expected = "about byte 0x78 ('x') at index 2: it is matched by the fallback but not by the optimized search")]
+    fn verify_reports_disagreement_when_rejecting() {
+        let trimmed = "  x".trim_start_matches(SPACE.with_fallback(|c| c == b' ' || c == b'x'));
+        assert_eq!(trimmed, "x");
+    }
+
+    #[test]
+    #[cfg(all(feature = "unstable", feature = "verify"))]
+    #[should_panic(expected = "disagree about byte 0x3e ('>') at index 3")]
+    fn verify_reports_disagreement_when_searching_backwards() {
+        let parts: Vec<_> = "a<b>c".rsplit(XML_DELIM_3.with_fallback(|c| c == b'<')).collect();
+        drop(parts);
+    }
 
-        let matching_bytes: usize;
+    #[test]
+    fn works_with_sixteen_characters() {
+        let delimiters = b"<>&'\"=/ \t\r\n?#[]@";
+        let mut searcher = AsciiChars::new();
+        for &b in delimiters {
+            searcher.push(b);
+        }
+
+        for &b in delimiters {
+            let haystack = format!("0123456789abcdefghij{}", b as char);
+            assert_eq!(Some(20), searcher.find(&haystack));
+            assert_eq!(Some(20), searcher.rfind(&haystack));
+        }
+        assert_eq!(None, searcher.find("0123456789abcdefghij"));
+        assert_eq!(None, searcher.rfind("0123456789abcdefghij"));
+    }
 
-        unsafe {
-            asm!("pcmpestrm $$0, ($1), $2;"
-                 : // output operands
-                 "={xmm0}"(matching_bytes)
-                 : // input operands
-                 "r"(ptr),
-                 "x"(self.needle),
-                 "{rdx}"(16),
-                 "{rax}"(self.count)
-                 : // clobbers
-                 : // options
-            );
-        }
-
-        // Ignore matches that occurred before our string began
-        let matching_bytes = matching_bytes >> offset;
-
-        if matching_bytes != 0 {
-            // Matched somewhere in there, pull out the index
-            let index = matching_bytes.trailing_zeros() as usize;
-
-            if index > len {
-                // We matched, but not within our own string
-                return InitialMatch::Complete(None);
-            } else {
-                return InitialMatch::Complete(Some(index));
-            }
+    #[test]
+    #[should_panic]
+    fn panics_when_adding_a_seventeenth_character() {
+        let mut searcher = AsciiChars::new();
+        for b in b'a'..=b'q' {
+            searcher.push(b);
         }
+    }
 
-        let length_of_leading_str = 16 - offset;
+    #[test]
+    fn from_bytes_is_the_same_as_pushing() {
+        const CHAINED: AsciiChars = AsciiChars::new().with(b'<').with(b'>').with(b'&');
+        let mut pushed = AsciiChars::new();
+        for &b in b"<>&" {
+            pushed.push(b);
+        }
 
-        if len < length_of_leading_str {
-            // We've searched the entire string
-            InitialMatch::Complete(None)
-        } else {
-            InitialMatch::Incomplete(length_of_leading_str)
+        for set in &[XML_DELIM_3, CHAINED, pushed] {
+            assert_eq!(Some(2), set.find("ab&cd<ef>"));
+            assert_eq!(Some(8), set.rfind("ab&cd<ef>"));
         }
     }
-}
 
-impl fmt::Debug for AsciiChars {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "AsciiChars {{ needle: 0x{:016x}, count: {} }}", self.needle, self.count)
+    #[test]
+    fn try_push_reports_each_error() {
+        let mut chars = AsciiChars::new();
+        assert_eq!(Err(AsciiCharsError::NonAscii { byte: 0x80 }), chars.try_push(0x80));
+        for b in b'a'..=b'p' {
+            assert_eq!(Ok(()), chars.try_push(b));
+        }
+        assert_eq!(Err(AsciiCharsError::Duplicate), chars.try_push(b'a'));
+        assert_eq!(Err(AsciiCharsError::CapacityExceeded { max: 16 }), chars.try_push(b'q'));
+        assert_eq!(Some(16), chars.find("ABCDEFGHIJKLMNOPp"));
     }
-}
 
-/// Provides a hook for a user-supplied fallback implementation, used
-/// when the optimized instructions are not available.
-///
-/// Although this implementation is a bit ungainly, Rust's closure
-/// inlining is top-notch and provides the best speed.
-#[derive(Debug,Copy,Clone)]
-pub struct AsciiCharsWithFallback<F> {
-    inner: AsciiChars,
-    fallback: F,
-}
-
-impl<'a, F> Pattern<'a> for AsciiCharsWithFallback<F>
-    where F: Fn(u8) -> bool
-{
-    type Searcher = AsciiCharsSearcher<'a, F>;
+    #[test]
+    fn try_from_builds_the_set() {
+        let chars = AsciiChars::try_from(&b"<>&"[..]).unwrap();
+        assert_eq!(Some(2), chars.find("ab&cd<ef>"));
 
-    fn into_searcher(self, haystack: &'a str) -> AsciiCharsSearcher<'a, F> {
-        AsciiCharsSearcher { haystack: haystack, offset: 0, needle: self }
+        let err = AsciiChars::try_from("ü".as_bytes()).unwrap_err();
+        assert_eq!(AsciiCharsError::NonAscii { byte: 0xc3 }, err);
+        assert_eq!("byte 0xc3 is not ASCII", err.to_string());
     }
-}
 
-/// An implementation of `Searcher` using `AsciiChars`
-#[derive(Debug,Copy,Clone)]
-pub struct AsciiCharsSearcher<'a, F> {
-    haystack: &'a str,
-    offset: usize,
-    needle: AsciiCharsWithFallback<F>,
-}
+    #[test]
+    fn try_extend_leaves_the_set_unchanged_on_error() {
+        let mut chars = XML_DELIM_3;
+        assert_eq!(Err(AsciiCharsError::Duplicate), chars.try_extend(b"'\"<".iter().cloned()));
+        assert_eq!(None, chars.find("'\""));
 
-impl<'a, F> AsciiCharsSearcher<'a, F>
-    where F: Fn(u8) -> bool
-{
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
-    fn next_idx(&self, haystack: &str) -> Option<usize> {
-        self.needle.inner.find(haystack)
+        assert_eq!(Ok(()), chars.try_extend(b"'\"".iter().cloned()));
+        assert_eq!(Some(0), chars.find("'\""));
     }
 
-    #[cfg(not(all(feature = "unstable", target_arch = "x86_64")))]
-    fn next_idx(&self, haystack: &str) -> Option<usize> {
-        haystack.as_bytes().iter().cloned().position(&self.needle.fallback)
+    #[test]
+    fn collects_from_bytes() {
+        let chars: AsciiChars = b"<>&".iter().cloned().collect();
+        assert_eq!(Some(2), chars.find("ab&cd<ef>"));
+        assert_eq!(Some(AsciiCharsError::CapacityExceeded { max: 16 }), AsciiChars::try_from_iter(b'a'..=b'z').err());
     }
-}
-
-unsafe impl<'a, F> Searcher<'a> for AsciiCharsSearcher<'a, F>
-    where F: Fn(u8) -> bool
-{
-    fn haystack(&self) -> &'a str { self.haystack }
-
-    #[inline]
-    fn next(&mut self) -> SearchStep {
-        if self.offset >= self.haystack.len() { return SearchStep::Done }
 
-        let left_to_search = &self.haystack[self.offset..]; // TODO: unchecked_slice?
-        let idx = self.next_idx(left_to_search);
-
-        // If there's no match, then the rest of the string should be
-        // returned.
-        let idx = idx.unwrap_or(self.haystack.len());
-
-        let (res, next_offset) = if idx == 0 {
-            // A match occurs at the beginning of the string
-            let next = self.offset + 1;
-            (SearchStep::Match(self.offset, next), next)
-        } else {
-            // A match occurs somewhere further in the string
-            let next = self.offset + idx;
-            (SearchStep::Reject(self.offset, next), next)
-        };
+    #[test]
+    fn parses_a_character_class() {
+        let chars: AsciiChars = "[<>&]".parse().unwrap();
+        assert_eq!(Some(2), chars.find("ab&cd<ef>"));
+        assert_eq!("[&<>]", chars.to_string());
 
-        self.offset = next_offset;
-        res
+        let chars: AsciiChars = r"\x00-\x03 ".parse().unwrap();
+        assert_eq!(r"[\x00-\x03 ]", chars.to_string());
     }
-}
 
-#[cfg(test)]
-mod test {
-    extern crate quickcheck;
-    extern crate libc;
-    extern crate rand;
+    #[test]
+    fn parsing_reports_sets_that_do_not_fit() {
+        assert_eq!(Some(ParseSetError::Set(AsciiCharsError::CapacityExceeded { max: 16 })),
+                   "[a-z]".parse::<AsciiChars>().err());
+        assert_eq!(Some(ParseSetError::Set(AsciiCharsError::NonAscii { byte: 0x80 })),
+                   r"[\x80]".parse::<AsciiChars>().err());
+        assert_eq!(Some(ParseSetError::UnexpectedEnd), "[abc".parse::<AsciiChars>().err());
+    }
 
-    use super::AsciiChars;
-    use self::rand::Rng;
-    use self::quickcheck::{quickcheck,Arbitrary,Gen};
-    use std::str::pattern::{Pattern,Searcher,SearchStep};
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
-    use std::{slice,str,ptr};
+    #[test]
+    fn display_round_trips() {
+        fn prop(chars: Vec<AsciiChar>) -> bool {
+            let mut searcher = AsciiChars::new();
+            for c in chars.iter().take(16) {
+                searcher.push(c.0);
+            }
+            let parsed: AsciiChars = searcher.to_string().parse().unwrap();
+            parsed.to_string() == searcher.to_string()
+        }
+        quickcheck(prop as fn(Vec<AsciiChar>) -> bool);
+    }
 
-    pub const SPACE: AsciiChars       = AsciiChars { needle: 0x0000000000000020, count: 1 };
-    // < > &
-    pub const XML_DELIM_3: AsciiChars = AsciiChars { needle: 0x00000000003c3e26, count: 3 };
-    // < > & ' "
-    pub const XML_DELIM_5: AsciiChars = AsciiChars { needle: 0x0000003c3e262722, count: 5 };
+    #[test]
+    fn sets_are_equal_regardless_of_order() {
+        assert_eq!(XML_DELIM_3, AsciiChars::from_bytes(b"&><"));
+        assert_eq!(XML_DELIM_3, AsciiChars::from_bytes(b"<>&<"));
+        assert!(XML_DELIM_3 != XML_DELIM_5);
+
+        let mut sets = HashSet::new();
+        sets.insert(XML_DELIM_3);
+        assert!(sets.contains(&AsciiChars::from_bytes(b"&><&")));
+    }
 
-    #[derive(Debug,Copy,Clone)]
-    struct AsciiChar(u8);
+    #[test]
+    fn membership_can_be_queried() {
+        let set = AsciiChars::from_bytes(b"<>&<");
+        assert!(set.contains(b'&'));
+        assert!(!set.contains(b'"'));
+        assert_eq!(3, set.len());
+        assert!(!set.is_empty());
+        assert!(AsciiChars::new().is_empty());
+        assert_eq!(b"&<>", &set.iter().collect::<Vec<_>>()[..]);
+        assert_eq!(b"><&", &set.iter().rev().collect::<Vec<_>>()[..]);
+    }
 
-    impl Arbitrary for AsciiChar {
-        fn arbitrary<G>(g: &mut G) -> AsciiChar
-            where G: Gen
-        {
-            AsciiChar(g.gen_range(0, 128))
+    #[test]
+    fn set_algebra_works_as_on_hash_sets() {
+        fn prop(a: Vec<AsciiChar>, b: Vec<AsciiChar>) -> bool {
+            let a: AsciiChars = a.iter().take(16).map(|c| c.0).collect();
+            let b: AsciiChars = b.iter().take(16).map(|c| c.0).collect();
+            let (hash_a, hash_b): (HashSet<u8>, HashSet<u8>) = (a.iter().collect(), b.iter().collect());
+
+            let union: ByteSet = hash_a.union(&hash_b).cloned().collect();
+            let intersection: AsciiChars = hash_a.intersection(&hash_b).cloned().collect();
+            let difference: AsciiChars = hash_a.difference(&hash_b).cloned().collect();
+            let complement: ByteSet = (0..=255).filter(|b| !hash_a.contains(b)).collect();
+
+            a.len() == hash_a.len() &&
+                a.union(b) == union &&
+                a.intersection(b) == intersection &&
+                a.difference(b) == difference &&
+                a.complement() == complement
         }
+        quickcheck(prop as fn(Vec<AsciiChar>, Vec<AsciiChar>) -> bool);
     }
 
     #[test]
-    fn works_as_find_does_for_single_characters() {
-        // Quickcheck currently only generates Strings with A-Z, a-z, 0-9
-        fn prop(s: String, c: AsciiChar) -> bool {
-            let mut searcher = AsciiChars::new();
-            searcher.push(c.0);
-            s.find(searcher.with_fallback(|b| b == c.0)) == s.find(c.0 as char)
-        }
-        quickcheck(prop as fn(String, AsciiChar) -> bool);
+    fn debug_shows_the_bytes() {
+        assert_eq!(format!("{:?}", XML_DELIM_5), r#"AsciiChars(b"<>&\'\"")"#);
+        assert_eq!(format!("{:?}", AsciiChars::from_bytes(b"\0\t")), r#"AsciiChars(b"\x00\t")"#);
     }
 
     #[test]
-    fn works_as_find_does_for_multiple_characters() {
-        // Quickcheck currently only generates Strings with A-Z, a-z, 0-9
-        fn prop(s: String, (c1, c2, c3, c4): (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool {
-            let mut searcher = AsciiChars::new();
-            searcher.push(c1.0);
-            searcher.push(c2.0);
-            searcher.push(c3.0);
-            searcher.push(c4.0);
-            s.find(searcher.with_fallback(|b| b == c1.0 || b == c2.0 || b == c3.0 || b == c4.0)) == s.find(&[c1.0 as char, c2.0 as char, c3.0 as char, c4.0 as char][..])
-        }
-        quickcheck(prop as fn(String, (AsciiChar, AsciiChar, AsciiChar, AsciiChar)) -> bool);
+    #[should_panic(expected = "ASCII")]
+    fn from_bytes_panics_with_a_non_ascii_byte() {
+        AsciiChars::from_bytes(b"abc\xff");
     }
 
     #[test]
     fn can_search_for_nul_bytes() {
         let mut s = AsciiChars::new();
         s.push(b'\0');
-        assert_eq!(Some(1), "a\0".find(s.with_fallback(|b| b == b'\0')));
-        assert_eq!(Some(0), "\0".find(s.with_fallback(|b| b == b'\0')));
-        assert_eq!(None, "".find(s.with_fallback(|b| b == b'\0')));
+        assert_eq!(Some(1), s.find("a\0"));
+        assert_eq!(Some(0), s.find("\0"));
+        assert_eq!(None, s.find(""));
     }
 
     #[test]
     fn can_search_in_nul_bytes() {
         let mut s = AsciiChars::new();
         s.push(b'a');
-        assert_eq!(Some(1), "\0a".find(s.with_fallback(|b| b == b'a')));
-        assert_eq!(None, "\0".find(s.with_fallback(|b| b == b'a')));
+        assert_eq!(Some(1), s.find("\0a"));
+        assert_eq!(None, s.find("\0"));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn can_search_for_nul_bytes_as_pattern() {
+        let mut s = AsciiChars::new();
+        s.push(b'\0');
+        assert_eq!(Some(1), "a\0".find(s.with_fallback(|b| b == b'\0')));
+        assert_eq!(Some(0), "\0".find(s.with_fallback(|b| b == b'\0')));
+        assert_eq!(None, "".find(s.with_fallback(|b| b == b'\0')));
     }
 
     #[test]
+    #[cfg(feature = "unstable")]
     fn pattern_does_not_backtrack_after_first() {
         let mut searcher = SPACE.with_fallback(|b| b == b' ').into_searcher("hello w ");
         assert_eq!(SearchStep::Reject(0,5), searcher.next());
@@ -365,7 +1156,6 @@ mod test {
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn space_is_found() {
         // Since the algorithm operates on 16-byte chunks, it's
         // important to cover tests around that boundary. Since 16
@@ -393,7 +1183,6 @@ mod test {
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn space_not_found() {
         // Since the algorithm operates on 16-byte chunks, it's
         // important to cover tests around that boundary. Since 16
@@ -421,7 +1210,6 @@ mod test {
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn works_on_nonaligned_beginnings() {
         // We have special code for strings that don't lie on 16-byte
         // boundaries. Since allocation seems to happen on these
@@ -450,7 +1238,58 @@ mod test {
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    fn space_is_found_from_the_end() {
+        assert_eq!(Some(0),  SPACE.rfind(" "));
+        assert_eq!(Some(0),  SPACE.rfind(" 0"));
+        assert_eq!(Some(0),  SPACE.rfind(" 01"));
+        assert_eq!(Some(0),  SPACE.rfind(" 012"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123"));
+        assert_eq!(Some(0),  SPACE.rfind(" 01234"));
+        assert_eq!(Some(0),  SPACE.rfind(" 012345"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456"));
+        assert_eq!(Some(0),  SPACE.rfind(" 01234567"));
+        assert_eq!(Some(0),  SPACE.rfind(" 012345678"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789A"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789AB"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789ABC"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789ABCD"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789ABCDE"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789ABCDEF"));
+        assert_eq!(Some(0),  SPACE.rfind(" 0123456789ABCDEFG"));
+        assert_eq!(Some(17), SPACE.rfind(" 0123456789ABCDEF "));
+        assert_eq!(None,     SPACE.rfind(""));
+        assert_eq!(None,     SPACE.rfind("0123456789ABCDEFG"));
+    }
+
+    #[test]
+    fn works_on_nonaligned_beginnings_from_the_end() {
+        let s = " 0123456789ABCDEF".to_string();
+
+        for offset in 0..s.len() {
+            let expected = if offset == 0 { Some(0) } else { None };
+            assert_eq!(expected, SPACE.rfind(&s[offset..]), "offset {}", offset);
+            assert_eq!(expected, SPACE.rfind(&s[offset..offset + 1]), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn space_is_skipped() {
+        for len in 0..34 {
+            let spaces = " ".repeat(len);
+            assert_eq!(None, SPACE.find_not(&spaces), "{} spaces", len);
+            assert_eq!(None, SPACE.rfind_not(&spaces), "{} spaces", len);
+
+            let s = format!("{}a", spaces);
+            assert_eq!(Some(len), SPACE.find_not(&s), "{} spaces", len);
+            assert_eq!(Some(len), SPACE.rfind_not(&s), "{} spaces", len);
+            for offset in 0..len {
+                assert_eq!(Some(len - offset), SPACE.find_not(&s[offset..]), "{} spaces from {}", len, offset);
+            }
+        }
+    }
+
+    #[test]
     fn xml_delim_3_is_found() {
         assert_eq!(Some(0), XML_DELIM_3.find("<"));
         assert_eq!(Some(0), XML_DELIM_3.find(">"));
@@ -459,7 +1298,6 @@ mod test {
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn xml_delim_5_is_found() {
         assert_eq!(Some(0), XML_DELIM_5.find("<"));
         assert_eq!(Some(0), XML_DELIM_5.find(">"));
@@ -469,14 +1307,14 @@ mod test {
         assert_eq!(None,    XML_DELIM_5.find(""));
     }
 
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    #[cfg(unix)]
     #[cfg(target_os = "macos")]
-    const MAP_ANONYMOUS: libc::int32_t = libc::MAP_ANON;
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    const MAP_ANONYMOUS: libc::c_int = libc::MAP_ANON;
+    #[cfg(unix)]
     #[cfg(not(target_os = "macos"))]
-    const MAP_ANONYMOUS: libc::int32_t = libc::MAP_ANONYMOUS;
+    const MAP_ANONYMOUS: libc::c_int = libc::MAP_ANONYMOUS;
 
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    #[cfg(unix)]
     fn alloc_guarded_string(value: &str, protect: bool) -> &'static str {
         // Allocate a string that ends directly before a
         // read-protected page.
@@ -490,7 +1328,7 @@ mod test {
         unsafe {
             // Map two rw-accessible pages of anonymous memory
             let first_page = libc::mmap(
-                /* addr   = */ 0 as *mut libc::c_void,
+                /* addr   = */ ptr::null_mut(),
                 /* length = */ 2 * PAGE_SIZE as libc::size_t,
                 /* prot   = */ libc::PROT_READ | libc::PROT_WRITE,
                 /* flags  = */ libc::MAP_PRIVATE | MAP_ANONYMOUS,
@@ -499,7 +1337,7 @@ mod test {
                 );
             assert!(!first_page.is_null());
 
-            let second_page = first_page.offset(PAGE_SIZE as isize);
+            let second_page = first_page.add(PAGE_SIZE);
 
             if protect {
                 // Prohibit any access to the second page, so that any attempt
@@ -513,14 +1351,14 @@ mod test {
             }
 
             // Copy bytes to the end of the first page
-            let start = second_page.offset(-(value.len() as isize)) as *mut u8;
+            let start = second_page.sub(value.len()) as *mut u8;
             ptr::copy_nonoverlapping(value.as_ptr(), start, value.len());
             str::from_utf8_unchecked(slice::from_raw_parts(start, value.len()))
         }
     }
 
     #[test]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
+    #[cfg(unix)]
     fn works_at_page_boundary() {
         // PCMP*STR* instructions are known to read 16 bytes at a time.
         // This behaviour may cause accidental segfaults by reading
@@ -534,27 +1372,121 @@ mod test {
         // To verify this test, set protect=false to prevent segfaults.
         let text = alloc_guarded_string("0123456789abcdef", true);
 
-        // Will search for the last char
-        let mut needle = AsciiChars::new();
-        needle.push(b'f');
+        // Will search for the last char, in a set scattered enough to be
+        // searched with PCMPESTRI
+        let needle = AsciiChars::from_bytes(b"f<>&'");
+        assert_eq!(Strategy::Set, needle.strategy());
 
         // Check all suffixes of our 16-byte string
         for offset in 0..text.len() {
             let tail = &text[offset..];
             assert_eq!(Some(tail.len() - 1), needle.find(tail));
+            assert_eq!(Some(tail.len() - 1), needle.rfind(tail));
+        }
+    }
+
+    // Long enough to be searched 64 bytes at a time, with the only
+    // match in the last byte before the protected page.
+    #[cfg(unix)]
+    fn alloc_guarded_haystack() -> &'static [u8] {
+        alloc_guarded_string(&["a".repeat(199), "f".to_string()].concat(), true).as_bytes()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn every_strategy_works_at_page_boundary() {
+        let text = alloc_guarded_haystack();
+
+        for &bytes in &[&b"f"[..], b"efg", b"f<>", b"f<>&'"] {
+            let finder = Finder::new(AsciiChars::from_bytes(bytes));
+
+            for offset in 0..text.len() {
+                let tail = &text[offset..];
+                let last = tail.len() - 1;
+                assert_eq!(Some(last), finder.find(tail));
+                assert_eq!(Some(last), finder.rfind(tail));
+                assert_eq!(1, finder.count(tail));
+                assert!(finder.find_iter(tail).eq(Some((last, b'f'))));
+                assert!(finder.find_iter(tail).rev().eq(Some((last, b'f'))));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(unix, target_arch = "x86_64"))]
+    fn kernels_work_at_page_boundary() {
+        let text = alloc_guarded_haystack();
+        let sse42 = is_x86_feature_detected!("sse4.2");
+        let avx2 = is_x86_feature_detected!("avx2");
+        let AsciiChars { needle, count, .. } = AsciiChars::from_bytes(b"f<>");
+        let scattered = AsciiChars::from_bytes(b"f<>&'");
+
+        fn check<S, C, B>(tail: &[u8], width: usize, search: S, count: C, block: B)
+            where S: Fn(Search) -> Option<usize>,
+                  C: Fn() -> usize,
+                  B: Fn(usize) -> Matches
+        {
+            let last = tail.len() - 1;
+            assert_eq!(Some(last), search(Search::Find));
+            assert_eq!(Some(last), search(Search::Rfind));
+            assert_eq!(1, count());
+            assert_eq!([last], *by_blocks(tail, width, block));
+        }
+
+        for offset in 0..text.len() {
+            let tail = &text[offset..];
+
+            unsafe {
+                for compiled in &[sse2::Compiled::byte(b'f'), sse2::Compiled::range(b'e', b'g'), sse2::Compiled::chars(needle, count)] {
+                    check(tail, 16, |s| compiled.search(s, tail), || compiled.count(tail), |idx| compiled.block(tail, idx));
+                }
+                if sse42 {
+                    let loaded = sse42::Needle::new(scattered.needle, scattered.count);
+                    check(tail, 16, |s| loaded.search(s, tail), || loaded.count(tail), |idx| loaded.block(tail, idx));
+                }
+                if avx2 {
+                    for compiled in &[avx2::Compiled::byte(b'f'), avx2::Compiled::range(b'e', b'g'), avx2::Compiled::chars(needle, count)] {
+                        check(tail, 32, |s| compiled.search(s, tail), || compiled.count(tail), |idx| compiled.block(tail, idx));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn byte_set_works_at_page_boundary() {
+        // Blocks of up to 32 bytes are read at once
+        let text = alloc_guarded_string("0123456789ABCDEF0123456789abcdef", true);
+
+        let mut needle = ByteSet::new();
+        needle.insert(b'f');
+
+        for offset in 0..text.len() {
+            let tail = &text[offset..];
+            assert_eq!(Some(tail.len() - 1), needle.find(tail));
+            assert_eq!(Some(tail.len() - 1), needle.rfind(tail));
+            assert_eq!(1, needle.count(tail));
+            assert!(needle.find_iter(tail).eq(Some((tail.len() - 1, b'f'))));
+            assert!(needle.find_iter(tail).rev().eq(Some((tail.len() - 1, b'f'))));
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "unstable"))]
 mod bench {
     extern crate test;
 
+    use super::{ByteSet,Finder,Substring};
+    #[cfg(target_arch = "x86_64")]
+    use super::{avx2,sse2,sse42};
+    use super::AsciiChars;
     use super::test::{SPACE,XML_DELIM_3,XML_DELIM_5};
-    use std::iter;
+    #[cfg(target_arch = "x86_64")]
+    use dispatch::Search;
 
     fn prefix_string() -> String {
-        iter::repeat("a").take(5 * 1024 * 1024).collect()
+        "a".repeat(5 * 1024 * 1024)
     }
 
     fn bench_space<F>(b: &mut test::Bencher, f: F)
@@ -568,11 +1500,40 @@ mod bench {
     }
 
     #[bench]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn space_asciichars(b: &mut test::Bencher) {
         bench_space(b, |hs| SPACE.find(hs))
     }
 
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn space_asciichars_sse42(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        bench_space(b, |hs| unsafe { sse42::find::<sse42::EqualAny>(SPACE.needle, SPACE.count, hs.as_bytes()) })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn space_asciichars_avx2(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        bench_space(b, |hs| unsafe { avx2::Compiled::chars(SPACE.needle, SPACE.count).search(Search::Find, hs.as_bytes()) })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn space_asciichars_avx2_byte(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        bench_space(b, |hs| unsafe { avx2::Compiled::byte(b' ').search(Search::Find, hs.as_bytes()) })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn space_asciichars_sse2_byte(b: &mut test::Bencher) {
+        bench_space(b, |hs| unsafe { sse2::Compiled::byte(b' ').search(Search::Find, hs.as_bytes()) })
+    }
+
     #[bench]
     fn space_asciichars_as_pattern(b: &mut test::Bencher) {
         bench_space(b, |hs| hs.find(SPACE.with_fallback(|b| b == b' ')))
@@ -598,11 +1559,57 @@ mod bench {
         bench_space(b, |hs| hs.as_bytes().iter().position(|&v| v == b' '))
     }
 
+    // The closure is the pattern being measured
     #[bench]
+    #[allow(clippy::manual_pattern_char_comparison)]
     fn space_find_closure(b: &mut test::Bencher) {
         bench_space(b, |hs| hs.find(|c| c == ' '))
     }
 
+    const DIGITS: AsciiChars = AsciiChars::from_bytes(b"0123456789");
+
+    fn bench_digit<F>(b: &mut test::Bencher, f: F)
+        where F: Fn(&str) -> Option<usize>
+    {
+        let mut haystack = prefix_string();
+        haystack.push('7');
+
+        b.iter(|| test::black_box(f(&haystack)));
+        b.bytes = haystack.len() as u64;
+    }
+
+    #[bench]
+    fn digit_asciichars(b: &mut test::Bencher) {
+        bench_digit(b, |hs| DIGITS.find(hs))
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn digit_asciichars_sse42(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        bench_digit(b, |hs| unsafe { sse42::find::<sse42::EqualAny>(DIGITS.needle, DIGITS.count, hs.as_bytes()) })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn digit_asciichars_sse2_range(b: &mut test::Bencher) {
+        bench_digit(b, |hs| unsafe { sse2::Compiled::range(b'0', b'9').search(Search::Find, hs.as_bytes()) })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn digit_asciichars_avx2_range(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        bench_digit(b, |hs| unsafe { avx2::Compiled::range(b'0', b'9').search(Search::Find, hs.as_bytes()) })
+    }
+
+    #[bench]
+    fn digit_str_find(b: &mut test::Bencher) {
+        bench_digit(b, |hs| hs.find(|c: char| c.is_ascii_digit()))
+    }
+
     fn bench_xml_delim_3<F>(b: &mut test::Bencher, f: F)
         where F: Fn(&str) -> Option<usize>
     {
@@ -614,11 +1621,38 @@ mod bench {
     }
 
     #[bench]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn xml_delim_3_asciichars(b: &mut test::Bencher) {
         bench_xml_delim_3(b, |hs| XML_DELIM_3.find(hs))
     }
 
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn xml_delim_3_asciichars_sse42(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        bench_xml_delim_3(b, |hs| unsafe {
+            sse42::find::<sse42::EqualAny>(XML_DELIM_3.needle, XML_DELIM_3.count, hs.as_bytes())
+        })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn xml_delim_3_asciichars_avx2(b: &mut test::Bencher) {
+        if !is_x86_feature_detected!("avx2") { return }
+
+        bench_xml_delim_3(b, |hs| unsafe {
+            avx2::Compiled::chars(XML_DELIM_3.needle, XML_DELIM_3.count).search(Search::Find, hs.as_bytes())
+        })
+    }
+
+    #[bench]
+    #[cfg(target_arch = "x86_64")]
+    fn xml_delim_3_asciichars_sse2(b: &mut test::Bencher) {
+        bench_xml_delim_3(b, |hs| unsafe {
+            sse2::Compiled::chars(XML_DELIM_3.needle, XML_DELIM_3.count).search(Search::Find, hs.as_bytes())
+        })
+    }
+
     #[bench]
     fn xml_delim_3_asciichars_as_pattern(b: &mut test::Bencher) {
         bench_xml_delim_3(b, |hs| hs.find(XML_DELIM_3.with_fallback(|c| {
@@ -656,11 +1690,16 @@ mod bench {
     }
 
     #[bench]
-    #[cfg(all(feature = "unstable", target_arch = "x86_64"))]
     fn xml_delim_5_asciichars(b: &mut test::Bencher) {
         bench_xml_delim_5(b, |hs| XML_DELIM_5.find(hs))
     }
 
+    #[bench]
+    fn xml_delim_5_byteset(b: &mut test::Bencher) {
+        let set: ByteSet = b"<>&'\"".iter().cloned().collect();
+        bench_xml_delim_5(b, |hs| set.find(hs))
+    }
+
     #[bench]
     fn xml_delim_5_asciichars_as_pattern(b: &mut test::Bencher) {
         bench_xml_delim_5(b, |hs| hs.find(XML_DELIM_5.with_fallback(|c| {
@@ -686,4 +1725,147 @@ mod bench {
             c == '<' || c == '>' || c == '&' || c == '\'' || c == '"'
         }))
     }
+
+    fn bench_end_of_comment<F>(b: &mut test::Bencher, f: F)
+        where F: Fn(&str) -> Option<usize>
+    {
+        // Near misses make the substring search look more closely
+        let mut haystack = "a-a--a".repeat(5 * 1024 * 1024 / 6);
+        haystack.push_str("-->");
+
+        b.iter(|| test::black_box(f(&haystack)));
+        b.bytes = haystack.len() as u64;
+    }
+
+    #[bench]
+    fn end_of_comment_substring(b: &mut test::Bencher) {
+        let search = Substring::new("-->");
+        bench_end_of_comment(b, |hs| search.find(hs))
+    }
+
+    #[bench]
+    fn end_of_comment_substring_as_pattern(b: &mut test::Bencher) {
+        let search = Substring::new("-->");
+        bench_end_of_comment(b, |hs| hs.find(search))
+    }
+
+    #[bench]
+    fn end_of_comment_find_string(b: &mut test::Bencher) {
+        bench_end_of_comment(b, |hs| hs.find("-->"))
+    }
+
+    fn bench_short_fields<F>(b: &mut test::Bencher, f: F)
+        where F: Fn(&str) -> Option<usize>
+    {
+        // Many short haystacks, where preparing the search dominates
+        let fields: Vec<String> = (0..100_000).map(|i| format!("field{}", i)).collect();
+
+        b.iter(|| {
+            for field in &fields {
+                test::black_box(f(field));
+            }
+        });
+        b.bytes = fields.iter().map(|f| f.len() as u64).sum();
+    }
+
+    #[bench]
+    fn short_fields_asciichars(b: &mut test::Bencher) {
+        bench_short_fields(b, |hs| XML_DELIM_5.find(hs))
+    }
+
+    #[bench]
+    fn short_fields_finder(b: &mut test::Bencher) {
+        let finder = Finder::new(XML_DELIM_5);
+        bench_short_fields(b, |hs| finder.find(hs))
+    }
+
+    #[bench]
+    fn dense_split_asciichars_as_pattern(b: &mut test::Bencher) {
+        // A match every few bytes, so stepping between them dominates
+        let haystack = "ab,cd,ef\n".repeat(1024 * 1024 / 9);
+        let search = AsciiChars::from_bytes(b",\n");
+
+        b.iter(|| test::black_box(haystack.split(search).count()));
+        b.bytes = haystack.len() as u64;
+    }
+
+    fn bench_dense_find_iter(b: &mut test::Bencher, chars: AsciiChars) {
+        let haystack = "ab,cd,ef\n".repeat(1024 * 1024 / 9);
+
+        b.iter(|| test::black_box(chars.find_iter(&haystack).count()));
+        b.bytes = haystack.len() as u64;
+    }
+
+    #[bench]
+    fn dense_find_iter_asciichars(b: &mut test::Bencher) {
+        bench_dense_find_iter(b, AsciiChars::from_bytes(b",\n"))
+    }
+
+    #[bench]
+    fn dense_find_from_asciichars(b: &mut test::Bencher) {
+        // Searching again after every match, as `find_iter` used to
+        let haystack = "ab,cd,ef\n".repeat(1024 * 1024 / 9);
+        let finder = Finder::new(AsciiChars::from_bytes(b",\n"));
+
+        b.iter(|| {
+            let (mut start, mut found) = (0, 0);
+            while let Some(idx) = finder.find_from(&haystack, start) {
+                start = idx + 1;
+                found += 1;
+            }
+            test::black_box(found)
+        });
+        b.bytes = haystack.len() as u64;
+    }
+
+    #[bench]
+    fn dense_find_iter_asciichars_byte(b: &mut test::Bencher) {
+        bench_dense_find_iter(b, AsciiChars::from_bytes(b","))
+    }
+
+    #[bench]
+    fn space_find_iter_asciichars(b: &mut test::Bencher) {
+        let mut haystack = prefix_string();
+        haystack.push(' ');
+
+        b.iter(|| test::black_box(SPACE.find_iter(&haystack).count()));
+        b.bytes = haystack.len() as u64;
+    }
+
+    fn bench_count_lines<F>(b: &mut test::Bencher, f: F)
+        where F: Fn(&str) -> usize
+    {
+        let haystack = "a line of some typical length, with a comma\n".repeat(100_000);
+
+        b.iter(|| test::black_box(f(&haystack)));
+        b.bytes = haystack.len() as u64;
+    }
+
+    #[bench]
+    fn count_lines_asciichars(b: &mut test::Bencher) {
+        let newline = AsciiChars::from_bytes(b"\n");
+        bench_count_lines(b, |hs| newline.count(hs))
+    }
+
+    #[bench]
+    fn count_lines_find_iter(b: &mut test::Bencher) {
+        let newline = AsciiChars::from_bytes(b"\n");
+        bench_count_lines(b, |hs| newline.find_iter(hs).count())
+    }
+
+    #[bench]
+    fn count_lines_filter(b: &mut test::Bencher) {
+        bench_count_lines(b, |hs| hs.bytes().filter(|&b| b == b'\n').count())
+    }
+
+    #[bench]
+    fn count_delimiters_asciichars(b: &mut test::Bencher) {
+        let delimiters = AsciiChars::from_bytes(b",\n");
+        bench_count_lines(b, |hs| delimiters.count(hs))
+    }
+
+    #[bench]
+    fn count_delimiters_filter(b: &mut test::Bencher) {
+        bench_count_lines(b, |hs| hs.bytes().filter(|&b| b == b',' || b == b'\n').count())
+    }
 }
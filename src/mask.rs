@@ -0,0 +1,240 @@
+//! Searching a haystack one aligned block at a time, using a bitmask
+//! of the matching bytes in each block.
+//!
+//! Like the `PCMPESTRI` search, only aligned blocks are ever read, so
+//! a read never crosses into another (potentially protected) page.
+//! Any bytes in the block that lie outside of the haystack are
+//! masked off.
+//!
+//! These functions are always inlined so that they are compiled with
+//! the target features of the function that calls them.
+
+use std::cmp;
+
+use dispatch::{Matches,Search};
+
+/// Tests every byte of an aligned block at once.
+pub trait Matcher {
+    /// The number of bytes in a block; a power of two no larger than 64.
+    const WIDTH: usize;
+
+    /// Returns a mask where bit `i` is set if byte `i` of the block
+    /// is a match.
+    ///
+    /// ### Safety
+    ///
+    /// `ptr` must be aligned to `WIDTH` and the CPU must support the
+    /// instructions used by the implementation.
+    unsafe fn mask(&self, ptr: *const u8) -> u64;
+}
+
+impl<M: Matcher> Matcher for &M {
+    const WIDTH: usize = M::WIDTH;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        (**self).mask(ptr)
+    }
+}
+
+/// Matches the bytes that the inner matcher does not.
+pub struct Invert<M>(pub M);
+
+impl<M: Matcher> Matcher for Invert<M> {
+    const WIDTH: usize = M::WIDTH;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        !self.0.mask(ptr) & low_bits(M::WIDTH)
+    }
+}
+
+/// The location of a haystack relative to the aligned block that
+/// contains its first byte.
+struct Bounds {
+    base: *const u8,
+    start: usize,
+    end: usize,
+}
+
+#[inline(always)]
+fn bounds<M: Matcher>(haystack: &[u8]) -> Bounds {
+    let ptr = haystack.as_ptr();
+    let start = ptr as usize & (M::WIDTH - 1);
+    Bounds {
+        base: ptr.wrapping_sub(start),
+        start,
+        end: start + haystack.len(),
+    }
+}
+
+#[inline(always)]
+fn low_bits(n: usize) -> u64 {
+    if n >= 64 { !0 } else { (1 << n) - 1 }
+}
+
+/// The bits of the block at `block` that are part of the haystack.
+#[inline(always)]
+fn valid(block: usize, bounds: &Bounds) -> u64 {
+    low_bits(bounds.end - block) & !low_bits(bounds.start.saturating_sub(block))
+}
+
+/// Find the index of the first matching byte.
+#[inline(always)]
+pub unsafe fn find<M: Matcher>(matcher: &M, haystack: &[u8]) -> Option<usize> {
+    // An empty slice may not point to readable memory at all
+    if haystack.is_empty() { return None }
+
+    let bounds = bounds::<M>(haystack);
+
+    // The first block may start before the haystack does
+    let mask = matcher.mask(bounds.base) & valid(0, &bounds);
+    if mask != 0 {
+        return Some(mask.trailing_zeros() as usize - bounds.start);
+    }
+    let mut block = M::WIDTH;
+
+    // The masks of 64 bytes' worth of blocks fit side by side in one
+    // `u64`, so they can be tested with a single branch.
+    while block + 64 <= bounds.end {
+        let mut mask = 0;
+        for i in 0..64 / M::WIDTH {
+            mask |= matcher.mask(bounds.base.wrapping_add(block + i * M::WIDTH)) << (i * M::WIDTH);
+        }
+        if mask != 0 {
+            return Some(block + mask.trailing_zeros() as usize - bounds.start);
+        }
+        block += 64;
+    }
+
+    while block < bounds.end {
+        let mask = matcher.mask(bounds.base.wrapping_add(block)) & valid(block, &bounds);
+        if mask != 0 {
+            return Some(block + mask.trailing_zeros() as usize - bounds.start);
+        }
+        block += M::WIDTH;
+    }
+
+    None
+}
+
+/// Find the index of the last matching byte.
+#[inline(always)]
+pub unsafe fn rfind<M: Matcher>(matcher: &M, haystack: &[u8]) -> Option<usize> {
+    if haystack.is_empty() { return None }
+
+    let bounds = bounds::<M>(haystack);
+    let mut block = (bounds.end - 1) & !(M::WIDTH - 1);
+
+    loop {
+        let mask = matcher.mask(bounds.base.wrapping_add(block)) & valid(block, &bounds);
+        if mask != 0 {
+            return Some(block + 63 - mask.leading_zeros() as usize - bounds.start);
+        }
+        if block == 0 {
+            return None;
+        }
+        block -= M::WIDTH;
+    }
+}
+
+/// Count the matching bytes.
+#[inline(always)]
+pub unsafe fn count<M: Matcher>(matcher: &M, haystack: &[u8]) -> usize {
+    if haystack.is_empty() { return 0 }
+
+    let bounds = bounds::<M>(haystack);
+    let mut total: usize = (matcher.mask(bounds.base) & valid(0, &bounds)).count_ones() as usize;
+    let mut block = M::WIDTH;
+
+    while block + 64 <= bounds.end {
+        let mut mask = 0;
+        for i in 0..64 / M::WIDTH {
+            mask |= matcher.mask(bounds.base.wrapping_add(block + i * M::WIDTH)) << (i * M::WIDTH);
+        }
+        total += mask.count_ones() as usize;
+        block += 64;
+    }
+
+    while block < bounds.end {
+        total += (matcher.mask(bounds.base.wrapping_add(block)) & valid(block, &bounds)).count_ones() as usize;
+        block += M::WIDTH;
+    }
+
+    total
+}
+
+/// Count the bytes that each matcher matches, adding each count to
+/// the tally in the same position.
+#[inline(always)]
+pub unsafe fn count_each<M: Matcher>(matchers: &[M], haystack: &[u8], tallies: &mut [usize]) {
+    if haystack.is_empty() { return }
+
+    let bounds = bounds::<M>(haystack);
+    let mut block = 0;
+
+    while block < bounds.end {
+        let ptr = bounds.base.wrapping_add(block);
+        let valid = valid(block, &bounds);
+        for (matcher, tally) in matchers.iter().zip(tallies.iter_mut()) {
+            *tally += (matcher.mask(ptr) & valid).count_ones() as usize;
+        }
+        block += M::WIDTH;
+    }
+}
+
+/// The matches in the aligned block that holds byte `idx` of the
+/// haystack, which must be in bounds.
+#[inline(always)]
+pub unsafe fn block<M: Matcher>(matcher: &M, haystack: &[u8], idx: usize) -> Matches {
+    let bounds = bounds::<M>(haystack);
+    let block = (bounds.start + idx) & !(M::WIDTH - 1);
+    let mask = matcher.mask(bounds.base.wrapping_add(block)) & valid(block, &bounds);
+
+    // Only the first block starts before the haystack does
+    let before = bounds.start.saturating_sub(block);
+    Matches {
+        start: block + before - bounds.start,
+        end: cmp::min(block + M::WIDTH, bounds.end) - bounds.start,
+        mask: mask >> before,
+    }
+}
+
+/// Performs the search with the matcher, or its inverse for the bytes
+/// not in the set.
+#[inline(always)]
+pub unsafe fn search<M: Matcher>(matcher: &M, search: Search, haystack: &[u8]) -> Option<usize> {
+    match search {
+        Search::Find => find(matcher, haystack),
+        Search::Rfind => rfind(matcher, haystack),
+        Search::FindNot => find(&Invert(matcher), haystack),
+        Search::RfindNot => rfind(&Invert(matcher), haystack),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{block,count,search,Matcher};
+    use dispatch::test::{by_blocks,positions,reference,SEARCHES};
+
+    /// Checks every search, the matches in each block and the count
+    /// against `f`, starting at each offset into an aligned block.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support the instructions used by the matcher.
+    #[inline(always)]
+    pub unsafe fn check<M, F>(matcher: &M, haystack: &[u8], f: F) -> bool
+        where M: Matcher,
+              F: Fn(u8) -> bool,
+    {
+        (0..M::WIDTH).all(|offset| {
+            let haystack = &haystack[offset.min(haystack.len())..];
+            let expected = positions(haystack, &f);
+
+            SEARCHES.iter().all(|&s| search(matcher, s, haystack) == reference(s, haystack, &f)) &&
+                by_blocks(haystack, M::WIDTH, |idx| block(matcher, haystack, idx)) == expected &&
+                count(matcher, haystack) == expected.len()
+        })
+    }
+}
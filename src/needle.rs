@@ -0,0 +1,67 @@
+//! The operations shared by the sets searched with `PCMPESTRI`.
+
+use std::fmt;
+
+use dispatch::Search;
+use {fallback,AsciiChars,AsciiRanges,Finder};
+
+/// A set of ASCII characters searched for using the SSE 4.2
+/// instructions.
+pub trait Needle: Copy + fmt::Debug {
+    /// The set made ready for the many searches of a searcher, so that
+    /// it is only prepared once.
+    type Prepared: Copy + fmt::Debug;
+    fn prepare(self) -> Self::Prepared;
+    fn search(prepared: &Self::Prepared, search: Search, haystack: &[u8]) -> Option<usize>;
+
+    fn find(self, haystack: &[u8]) -> Option<usize>;
+    fn rfind(self, haystack: &[u8]) -> Option<usize>;
+    fn find_not(self, haystack: &[u8]) -> Option<usize>;
+    fn rfind_not(self, haystack: &[u8]) -> Option<usize>;
+    /// Tests a single byte against the set, without any acceleration.
+    fn matches(self, byte: u8) -> bool;
+}
+
+impl Needle for AsciiChars {
+    type Prepared = Finder;
+    #[inline]
+    fn prepare(self) -> Finder { Finder::new(self) }
+    #[inline]
+    fn search(finder: &Finder, search: Search, haystack: &[u8]) -> Option<usize> { finder.search(search, haystack) }
+    #[inline]
+    fn find(self, haystack: &[u8]) -> Option<usize> { AsciiChars::find(self, haystack) }
+    #[inline]
+    fn rfind(self, haystack: &[u8]) -> Option<usize> { AsciiChars::rfind(self, haystack) }
+    #[inline]
+    fn find_not(self, haystack: &[u8]) -> Option<usize> { AsciiChars::find_not(self, haystack) }
+    #[inline]
+    fn rfind_not(self, haystack: &[u8]) -> Option<usize> { AsciiChars::rfind_not(self, haystack) }
+    #[inline]
+    fn matches(self, byte: u8) -> bool { fallback::matches(self.needle, self.count, byte) }
+}
+
+/// Ranges have no prepared form, so they are searched as they are.
+impl Needle for AsciiRanges {
+    type Prepared = AsciiRanges;
+    #[inline]
+    fn prepare(self) -> AsciiRanges { self }
+    #[inline]
+    fn search(ranges: &AsciiRanges, search: Search, haystack: &[u8]) -> Option<usize> {
+        match search {
+            Search::Find => ranges.find(haystack),
+            Search::Rfind => ranges.rfind(haystack),
+            Search::FindNot => ranges.find_not(haystack),
+            Search::RfindNot => ranges.rfind_not(haystack),
+        }
+    }
+    #[inline]
+    fn find(self, haystack: &[u8]) -> Option<usize> { AsciiRanges::find(self, haystack) }
+    #[inline]
+    fn rfind(self, haystack: &[u8]) -> Option<usize> { AsciiRanges::rfind(self, haystack) }
+    #[inline]
+    fn find_not(self, haystack: &[u8]) -> Option<usize> { AsciiRanges::find_not(self, haystack) }
+    #[inline]
+    fn rfind_not(self, haystack: &[u8]) -> Option<usize> { AsciiRanges::rfind_not(self, haystack) }
+    #[inline]
+    fn matches(self, byte: u8) -> bool { fallback::in_ranges(self.needle, self.count, byte) }
+}
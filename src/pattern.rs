@@ -0,0 +1,726 @@
+//! Integration with the standard library's `Pattern` API.
+
+use std::{fmt,str};
+use std::str::pattern::{Pattern,Searcher,ReverseSearcher,DoubleEndedSearcher,SearchStep};
+
+use dispatch::{self,Kernel,Search};
+use needle::Needle;
+use {AsciiChars,AsciiRanges,ByteSet,Substring};
+
+impl AsciiChars {
+    /// Builds a searcher with a fallback implementation for when the
+    /// optimized version is not available. The fallback should search
+    /// for the **exact** same set of characters.
+    ///
+    /// This is only needed to override the `LookupTable` that is used
+    /// when searching with an `AsciiChars` directly.
+    pub fn with_fallback<F>(self, fallback: F) -> AsciiCharsWithFallback<F>
+        where F: Fallback
+    {
+        WithFallback { inner: self, fallback }
+    }
+}
+
+impl AsciiRanges {
+    /// Builds a searcher with a fallback implementation for when the
+    /// optimized version is not available. The fallback should search
+    /// for the **exact** same set of characters.
+    ///
+    /// This is only needed to override the `LookupTable` that is used
+    /// when searching with an `AsciiRanges` directly.
+    pub fn with_fallback<F>(self, fallback: F) -> AsciiRangesWithFallback<F>
+        where F: Fallback
+    {
+        WithFallback { inner: self, fallback }
+    }
+}
+
+/// Tests a single byte, used when the optimized instructions are not
+/// available.
+///
+/// This is implemented for all closures of the form `Fn(u8) -> bool`.
+pub trait Fallback {
+    /// Returns true if the byte is part of the set being searched for.
+    fn matches(&self, byte: u8) -> bool;
+}
+
+impl<F> Fallback for F
+    where F: Fn(u8) -> bool
+{
+    #[inline]
+    fn matches(&self, byte: u8) -> bool { self(byte) }
+}
+
+/// A fallback derived from an `AsciiChars` or `AsciiRanges`, which
+/// tests each byte with a 256-entry table.
+///
+/// The searchers created when an `AsciiChars` or `AsciiRanges` is used
+/// as a `Pattern` only fill in the table when it will be read, which is
+/// under the fallback kernel or with the `verify` feature.
+#[derive(Copy,Clone)]
+pub struct LookupTable {
+    table: [bool; 256],
+}
+
+impl LookupTable {
+    pub fn new<N>(needle: N) -> LookupTable
+        where N: Needle
+    {
+        let mut table = [false; 256];
+        for (byte, entry) in (0..=255).zip(table.iter_mut()) {
+            *entry = needle.matches(byte);
+        }
+        LookupTable { table }
+    }
+
+    /// Filling in the table tests the needle against all 256 bytes,
+    /// which would cost more than searching a short haystack, so it is
+    /// left empty when the optimized search is used alone.
+    #[inline]
+    fn when_needed<N>(needle: N) -> LookupTable
+        where N: Needle
+    {
+        if cfg!(feature = "verify") || dispatch::kernel() == Kernel::Fallback {
+            LookupTable::new(needle)
+        } else {
+            LookupTable { table: [false; 256] }
+        }
+    }
+}
+
+impl Fallback for LookupTable {
+    #[inline]
+    fn matches(&self, byte: u8) -> bool { self.table[byte as usize] }
+}
+
+impl fmt::Debug for LookupTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<_> = (0..=255u8).filter(|&b| self.matches(b)).collect();
+        f.debug_struct("LookupTable").field("bytes", &bytes).finish()
+    }
+}
+
+impl Pattern for AsciiChars {
+    type Searcher<'a> = AsciiCharsSearcher<'a, LookupTable>;
+
+    fn into_searcher(self, haystack: &str) -> AsciiCharsSearcher<'_, LookupTable> {
+        self.with_fallback(LookupTable::when_needed(self)).into_searcher(haystack)
+    }
+}
+
+impl Pattern for AsciiRanges {
+    type Searcher<'a> = AsciiRangesSearcher<'a, LookupTable>;
+
+    fn into_searcher(self, haystack: &str) -> AsciiRangesSearcher<'_, LookupTable> {
+        self.with_fallback(LookupTable::when_needed(self)).into_searcher(haystack)
+    }
+}
+
+/// Provides a hook for a user-supplied fallback implementation, used
+/// when the optimized instructions are not available.
+///
+/// Although this implementation is a bit ungainly, Rust's closure
+/// inlining is top-notch and provides the best speed.
+#[derive(Debug,Copy,Clone)]
+pub struct WithFallback<N, F> {
+    inner: N,
+    fallback: F,
+}
+
+/// An `AsciiChars` with a user-supplied fallback.
+pub type AsciiCharsWithFallback<F> = WithFallback<AsciiChars, F>;
+
+/// An `AsciiRanges` with a user-supplied fallback.
+pub type AsciiRangesWithFallback<F> = WithFallback<AsciiRanges, F>;
+
+impl<N, F> Pattern for WithFallback<N, F>
+    where N: Needle,
+          F: Fallback
+{
+    type Searcher<'a> = FallbackSearcher<'a, N, F>;
+
+    fn into_searcher(self, haystack: &str) -> FallbackSearcher<'_, N, F> {
+        let prepared = self.inner.prepare();
+        FallbackSearcher { haystack, offset: 0, end: haystack.len(), needle: self, prepared }
+    }
+}
+
+/// An implementation of `Searcher` using a `WithFallback`
+#[derive(Debug,Copy,Clone)]
+pub struct FallbackSearcher<'a, N, F>
+    where N: Needle
+{
+    haystack: &'a str,
+    offset: usize,
+    end: usize,
+    needle: WithFallback<N, F>,
+    // Prepared once, rather than for each step of the search
+    prepared: N::Prepared,
+}
+
+/// An implementation of `Searcher` using `AsciiChars`
+pub type AsciiCharsSearcher<'a, F> = FallbackSearcher<'a, AsciiChars, F>;
+
+/// An implementation of `Searcher` using `AsciiRanges`
+pub type AsciiRangesSearcher<'a, F> = FallbackSearcher<'a, AsciiRanges, F>;
+
+impl<'a, N, F> FallbackSearcher<'a, N, F>
+    where N: Needle,
+          F: Fallback
+{
+    /// Searches the bytes from `offset` to `end`, returning an index
+    /// relative to `offset`.
+    #[inline]
+    fn search(&self, search: Search) -> Option<usize> {
+        let haystack = &self.haystack.as_bytes()[self.offset..self.end];
+
+        let kernel = dispatch::kernel();
+        let idx = match kernel {
+            Kernel::Fallback => self.fallback_search(search, haystack),
+            _ => self.optimized_search(search, haystack),
+        };
+
+        // Only the search that was not already made is repeated
+        #[cfg(feature = "verify")]
+        match kernel {
+            Kernel::Fallback => self.verify(haystack, search, self.optimized_search(search, haystack), idx),
+            _ => self.verify(haystack, search, idx, self.fallback_search(search, haystack)),
+        }
+
+        idx
+    }
+
+    #[inline]
+    fn optimized_search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        N::search(&self.prepared, search, haystack)
+    }
+
+    #[inline]
+    fn fallback_search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        let fallback = &self.needle.fallback;
+        let mut bytes = haystack.iter();
+        match search {
+            Search::Find => bytes.position(|&b| fallback.matches(b)),
+            Search::Rfind => bytes.rposition(|&b| fallback.matches(b)),
+            Search::FindNot => bytes.position(|&b| !fallback.matches(b)),
+            Search::RfindNot => bytes.rposition(|&b| !fallback.matches(b)),
+        }
+    }
+
+    /// Panics if the fallback does not agree with the optimized search
+    /// about where the next match is.
+    #[cfg(feature = "verify")]
+    fn verify(&self, haystack: &[u8], search: Search, optimized: Option<usize>, fallback: Option<usize>) {
+        if optimized == fallback { return }
+
+        let reverse = search == Search::Rfind || search == Search::RfindNot;
+        let not = search == Search::FindNot || search == Search::RfindNot;
+
+        // Whichever index is reached first in the direction of the
+        // search is the byte that only one of them thinks is a match.
+        let index = match (optimized, fallback) {
+            (Some(a), Some(b)) => if reverse { a.max(b) } else { a.min(b) },
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => unreachable!(),
+        };
+        // When searching for the bytes not in the set, the one that
+        // found the byte is the one that rejects it.
+        let (mut matched_by, mut rejected_by) = ("the fallback", "the optimized search");
+        if (optimized == Some(index)) != not {
+            std::mem::swap(&mut matched_by, &mut rejected_by);
+        }
+        let byte = haystack[index];
+
+        panic!("{:?} and its fallback disagree about byte {:#04x} ({:?}) at index {}: \
+                it is matched by {} but not by {}",
+               self.needle.inner, byte, byte as char, self.offset + index,
+               matched_by, rejected_by);
+    }
+}
+
+unsafe impl<'a, N, F> Searcher<'a> for FallbackSearcher<'a, N, F>
+    where N: Needle,
+          F: Fallback
+{
+    fn haystack(&self) -> &'a str { self.haystack }
+
+    #[inline]
+    fn next(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let len = self.end - self.offset;
+        let idx = self.search(Search::Find);
+        forward_step(&mut self.offset, len, idx)
+    }
+
+    /// Skips straight to the next match, rather than stopping at the
+    /// rejected bytes before it.
+    #[inline]
+    fn next_match(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        match self.search(Search::Find) {
+            Some(idx) => {
+                let start = self.offset + idx;
+                self.offset = start + 1;
+                Some((start, self.offset))
+            }
+            None => {
+                self.offset = self.end;
+                None
+            }
+        }
+    }
+
+    /// Skips straight to the next byte outside of the set, rejecting
+    /// everything up to the following match at once.
+    #[inline]
+    fn next_reject(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        let Some(idx) = self.search(Search::FindNot) else {
+            self.offset = self.end;
+            return None;
+        };
+        // Only ASCII bytes are in the set, so the reject starts and
+        // ends on character boundaries.
+        self.offset += idx;
+        let start = self.offset;
+        self.offset = self.search(Search::Find).map_or(self.end, |idx| start + idx);
+        Some((start, self.offset))
+    }
+}
+
+unsafe impl<'a, N, F> ReverseSearcher<'a> for FallbackSearcher<'a, N, F>
+    where N: Needle,
+          F: Fallback
+{
+    #[inline]
+    fn next_back(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let idx = self.search(Search::Rfind);
+        reverse_step(self.offset, &mut self.end, idx)
+    }
+
+    #[inline]
+    fn next_match_back(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        match self.search(Search::Rfind) {
+            Some(idx) => {
+                self.end = self.offset + idx;
+                Some((self.end, self.end + 1))
+            }
+            None => {
+                self.end = self.offset;
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn next_reject_back(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        let Some(idx) = self.search(Search::RfindNot) else {
+            self.end = self.offset;
+            return None;
+        };
+        self.end = self.offset + idx + 1;
+        let end = self.end;
+        self.end = self.search(Search::Rfind).map_or(self.offset, |idx| self.offset + idx + 1);
+        Some((self.end, end))
+    }
+}
+
+impl<'a, N, F> DoubleEndedSearcher<'a> for FallbackSearcher<'a, N, F>
+    where N: Needle,
+          F: Fallback
+{}
+
+/// Only the ASCII members of the set are searched for, as matching
+/// any other byte would split a character.
+impl Pattern for ByteSet {
+    type Searcher<'a> = ByteSetSearcher<'a>;
+
+    fn into_searcher(self, haystack: &str) -> ByteSetSearcher<'_> {
+        ByteSetSearcher { haystack, offset: 0, end: haystack.len(), set: self.ascii_only() }
+    }
+}
+
+/// An implementation of `Searcher` using `ByteSet`
+#[derive(Debug,Copy,Clone)]
+pub struct ByteSetSearcher<'a> {
+    haystack: &'a str,
+    offset: usize,
+    end: usize,
+    set: ByteSet,
+}
+
+impl<'a> ByteSetSearcher<'a> {
+    /// Searches the bytes from `offset` to `end`, returning an index
+    /// relative to `offset`.
+    #[inline]
+    fn search(&self, search: Search) -> Option<usize> {
+        self.set.search(search, &self.haystack.as_bytes()[self.offset..self.end])
+    }
+}
+
+unsafe impl<'a> Searcher<'a> for ByteSetSearcher<'a> {
+    fn haystack(&self) -> &'a str { self.haystack }
+
+    #[inline]
+    fn next(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let len = self.end - self.offset;
+        let idx = self.search(Search::Find);
+        forward_step(&mut self.offset, len, idx)
+    }
+
+    /// Skips straight to the next match, rather than stopping at the
+    /// rejected bytes before it.
+    #[inline]
+    fn next_match(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        match self.search(Search::Find) {
+            Some(idx) => {
+                let start = self.offset + idx;
+                self.offset = start + 1;
+                Some((start, self.offset))
+            }
+            None => {
+                self.offset = self.end;
+                None
+            }
+        }
+    }
+
+    /// Skips straight to the next byte outside of the set, rejecting
+    /// everything up to the following match at once.
+    #[inline]
+    fn next_reject(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        let Some(idx) = self.search(Search::FindNot) else {
+            self.offset = self.end;
+            return None;
+        };
+        // Only the ASCII members are searched for, so the reject starts
+        // and ends on character boundaries.
+        self.offset += idx;
+        let start = self.offset;
+        self.offset = self.search(Search::Find).map_or(self.end, |idx| start + idx);
+        Some((start, self.offset))
+    }
+}
+
+unsafe impl<'a> ReverseSearcher<'a> for ByteSetSearcher<'a> {
+    #[inline]
+    fn next_back(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let idx = self.search(Search::Rfind);
+        reverse_step(self.offset, &mut self.end, idx)
+    }
+
+    #[inline]
+    fn next_match_back(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        match self.search(Search::Rfind) {
+            Some(idx) => {
+                self.end = self.offset + idx;
+                Some((self.end, self.end + 1))
+            }
+            None => {
+                self.end = self.offset;
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn next_reject_back(&mut self) -> Option<(usize, usize)> {
+        if self.offset >= self.end { return None }
+
+        let Some(idx) = self.search(Search::RfindNot) else {
+            self.end = self.offset;
+            return None;
+        };
+        self.end = self.offset + idx + 1;
+        let end = self.end;
+        self.end = self.search(Search::Rfind).map_or(self.offset, |idx| self.offset + idx + 1);
+        Some((self.end, end))
+    }
+}
+
+impl<'a> DoubleEndedSearcher<'a> for ByteSetSearcher<'a> {}
+
+/// Searches for the characters that are *not* in an `AsciiChars` or
+/// `AsciiRanges`.
+///
+/// Every non-ASCII character is outside of the set, so it is matched
+/// as a whole.
+///
+/// ```
+/// use jetscii::{AsciiChars,Not};
+/// let mut whitespace = AsciiChars::new();
+/// whitespace.push(b' ');
+/// whitespace.push(b'\t');
+/// assert_eq!("  \tkey = value".find(Not(whitespace)), Some(3));
+/// ```
+#[derive(Debug,Copy,Clone)]
+pub struct Not<N>(pub N);
+
+impl<N> Pattern for Not<N>
+    where N: Needle
+{
+    type Searcher<'a> = NotSearcher<'a, N>;
+
+    fn into_searcher(self, haystack: &str) -> NotSearcher<'_, N> {
+        NotSearcher { haystack, offset: 0, end: haystack.len(), needle: self.0.prepare() }
+    }
+}
+
+/// An implementation of `Searcher` using `Not`
+#[derive(Debug,Copy,Clone)]
+pub struct NotSearcher<'a, N>
+    where N: Needle
+{
+    haystack: &'a str,
+    offset: usize,
+    end: usize,
+    needle: N::Prepared,
+}
+
+unsafe impl<'a, N> Searcher<'a> for NotSearcher<'a, N>
+    where N: Needle
+{
+    fn haystack(&self) -> &'a str { self.haystack }
+
+    #[inline]
+    fn next(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let left_to_search = &self.haystack[self.offset..self.end];
+        let idx = N::search(&self.needle, Search::FindNot, left_to_search.as_bytes());
+
+        match idx {
+            Some(0) => {
+                // The match is a whole character, which may be more
+                // than one byte
+                let width = left_to_search.chars().next().map_or(1, char::len_utf8);
+                let start = self.offset;
+                self.offset += width;
+                SearchStep::Match(start, self.offset)
+            }
+            _ => forward_step(&mut self.offset, left_to_search.len(), idx),
+        }
+    }
+}
+
+unsafe impl<'a, N> ReverseSearcher<'a> for NotSearcher<'a, N>
+    where N: Needle
+{
+    #[inline]
+    fn next_back(&mut self) -> SearchStep {
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let left_to_search = &self.haystack[self.offset..self.end];
+        let idx = N::search(&self.needle, Search::RfindNot, left_to_search.as_bytes());
+
+        match idx {
+            Some(idx) if idx + 1 == left_to_search.len() => {
+                // We found the last byte of the character, which may
+                // be more than one byte
+                let width = left_to_search.chars().next_back().map_or(1, char::len_utf8);
+                let end = self.end;
+                self.end -= width;
+                SearchStep::Match(self.end, end)
+            }
+            _ => reverse_step(self.offset, &mut self.end, idx),
+        }
+    }
+}
+
+impl<'a, N> DoubleEndedSearcher<'a> for NotSearcher<'a, N>
+    where N: Needle
+{}
+
+/// Matches are found from the start of the haystack and don't
+/// overlap, as with a `&str` pattern.
+///
+/// A needle that is not UTF-8 can only occur in the middle of a
+/// character, so it never matches.
+impl<'n> Pattern for Substring<'n> {
+    type Searcher<'a> = SubstringSearcher<'a, 'n>;
+
+    fn into_searcher(self, haystack: &str) -> SubstringSearcher<'_, 'n> {
+        SubstringSearcher {
+            haystack,
+            offset: 0,
+            end: haystack.len(),
+            needle: self,
+            utf8: str::from_utf8(self.as_bytes()).is_ok(),
+            empty_match_fw: true,
+            empty_match_bw: true,
+            empty_finished: false,
+        }
+    }
+}
+
+/// An implementation of `Searcher` using `Substring`
+#[derive(Debug,Copy,Clone)]
+pub struct SubstringSearcher<'a, 'n> {
+    haystack: &'a str,
+    offset: usize,
+    end: usize,
+    needle: Substring<'n>,
+    utf8: bool,
+    // An empty needle matches between every character, so its
+    // matches alternate with rejecting one character.
+    empty_match_fw: bool,
+    empty_match_bw: bool,
+    // Once either direction is done, both stay done.
+    empty_finished: bool,
+}
+
+unsafe impl<'a, 'n> Searcher<'a> for SubstringSearcher<'a, 'n> {
+    fn haystack(&self) -> &'a str { self.haystack }
+
+    #[inline]
+    fn next(&mut self) -> SearchStep {
+        let needle_len = self.needle.as_bytes().len();
+
+        if needle_len == 0 {
+            if self.empty_finished { return SearchStep::Done }
+
+            let is_match = self.empty_match_fw;
+            self.empty_match_fw = !is_match;
+
+            let start = self.offset;
+            return match self.haystack[start..self.end].chars().next() {
+                _ if is_match => SearchStep::Match(start, start),
+                None => {
+                    self.empty_finished = true;
+                    SearchStep::Done
+                }
+                Some(c) => {
+                    self.offset += c.len_utf8();
+                    SearchStep::Reject(start, self.offset)
+                }
+            };
+        }
+
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let left_to_search = &self.haystack[self.offset..self.end];
+        let idx = if self.utf8 { self.needle.find(left_to_search) } else { None };
+        let start = self.offset;
+
+        match idx {
+            Some(0) => {
+                self.offset += needle_len;
+                SearchStep::Match(start, self.offset)
+            }
+            _ => {
+                self.offset += idx.unwrap_or(left_to_search.len());
+                SearchStep::Reject(start, self.offset)
+            }
+        }
+    }
+}
+
+unsafe impl<'a, 'n> ReverseSearcher<'a> for SubstringSearcher<'a, 'n> {
+    #[inline]
+    fn next_back(&mut self) -> SearchStep {
+        let needle_len = self.needle.as_bytes().len();
+
+        if needle_len == 0 {
+            if self.empty_finished { return SearchStep::Done }
+
+            let is_match = self.empty_match_bw;
+            self.empty_match_bw = !is_match;
+
+            let end = self.end;
+            return match self.haystack[self.offset..end].chars().next_back() {
+                _ if is_match => SearchStep::Match(end, end),
+                None => {
+                    self.empty_finished = true;
+                    SearchStep::Done
+                }
+                Some(c) => {
+                    self.end -= c.len_utf8();
+                    SearchStep::Reject(self.end, end)
+                }
+            };
+        }
+
+        if self.offset >= self.end { return SearchStep::Done }
+
+        let left_to_search = &self.haystack[self.offset..self.end];
+        let idx = if self.utf8 { self.needle.rfind(left_to_search) } else { None };
+        let end = self.end;
+
+        // The end of the match starts the rejected region; if there's
+        // no match, then the rest of the string should be returned.
+        let match_end = idx.map_or(self.offset, |idx| self.offset + idx + needle_len);
+
+        if match_end == end {
+            self.end -= needle_len;
+            SearchStep::Match(self.end, end)
+        } else {
+            self.end = match_end;
+            SearchStep::Reject(match_end, end)
+        }
+    }
+}
+
+/// Converts the index of the next single-byte match in the `len`
+/// bytes starting at `offset` into a step, advancing `offset` past
+/// it.
+#[inline]
+fn forward_step(offset: &mut usize, len: usize, idx: Option<usize>) -> SearchStep {
+    // If there's no match, then the rest of the string should be
+    // returned.
+    let idx = idx.unwrap_or(len);
+
+    let (res, next_offset) = if idx == 0 {
+        // A match occurs at the beginning of the string
+        let next = *offset + 1;
+        (SearchStep::Match(*offset, next), next)
+    } else {
+        // A match occurs somewhere further in the string
+        let next = *offset + idx;
+        (SearchStep::Reject(*offset, next), next)
+    };
+
+    *offset = next_offset;
+    res
+}
+
+/// Converts the index of the previous single-byte match in the bytes
+/// from `offset` to `end` into a step, moving `end` before it.
+#[inline]
+fn reverse_step(offset: usize, end: &mut usize, idx: Option<usize>) -> SearchStep {
+    // The byte after the match starts the rejected region; if
+    // there's no match, then the rest of the string should be
+    // returned.
+    let idx = idx.map_or(0, |idx| idx + 1);
+
+    let (res, next_end) = if offset + idx == *end {
+        // A match occurs at the end of the string
+        let prev = *end - 1;
+        (SearchStep::Match(prev, *end), prev)
+    } else {
+        // A match occurs somewhere earlier in the string
+        let prev = offset + idx;
+        (SearchStep::Reject(prev, *end), prev)
+    };
+
+    *end = next_end;
+    res
+}
@@ -0,0 +1,341 @@
+//! Searching for ASCII characters within ranges.
+
+use std::{ascii,cmp,fmt};
+use std::str::FromStr;
+
+use class::Class;
+use dispatch;
+#[cfg(target_arch = "x86_64")]
+use dispatch::Kernel;
+use error::{AsciiCharsError,ParseSetError};
+use fallback;
+use iter::Runs;
+#[cfg(target_arch = "x86_64")]
+use sse42;
+
+/// Searches a string for ASCII characters within a set of inclusive
+/// ranges. Up to 8 ranges may be used.
+///
+/// ```
+/// use jetscii::AsciiRanges;
+/// let mut control = AsciiRanges::new();
+/// control.push_range(0x00, 0x1f);
+/// control.push_range(0x7f, 0x7f);
+/// assert_eq!(control.find("name\tvalue"), Some(4));
+/// ```
+///
+/// As with `AsciiChars`, a constant item can be created by chaining
+/// calls to `with_range`.
+#[derive(Copy,Clone)]
+pub struct AsciiRanges {
+    // Each range occupies two bytes of the needle, so `count` is
+    // twice the number of ranges.
+    pub(crate) needle: u128,
+    pub(crate) count: u8,
+}
+
+impl AsciiRanges {
+    pub const fn new() -> AsciiRanges {
+        AsciiRanges { needle: 0, count: 0 }
+    }
+
+    /// Returns the set with a new inclusive range of ASCII characters
+    /// added.
+    ///
+    /// ```
+    /// use jetscii::AsciiRanges;
+    /// const HEX_DIGITS: AsciiRanges = AsciiRanges::new()
+    ///     .with_range(b'0', b'9')
+    ///     .with_range(b'a', b'f');
+    /// assert_eq!(HEX_DIGITS.find("0xff"), Some(0));
+    /// assert_eq!(HEX_DIGITS.find_not("0xff"), Some(1));
+    /// ```
+    ///
+    /// ### Panics
+    ///
+    /// As for `push_range`.
+    pub const fn with_range(self, start: u8, end: u8) -> AsciiRanges {
+        assert!(end < 128, "AsciiRanges can only search for ASCII bytes");
+        assert!(start <= end, "the start of the range must not be after the end");
+        assert!(self.count < 16, "AsciiRanges can only search for up to 8 ranges");
+        AsciiRanges {
+            needle: self.needle << 16 | (end as u128) << 8 | start as u128,
+            count: self.count + 2,
+        }
+    }
+
+    /// Add a new inclusive range of ASCII characters to search for.
+    ///
+    /// ### Panics
+    ///
+    /// - If you add more than 8 ranges.
+    /// - If either end of the range is a non-ASCII byte.
+    /// - If the start of the range is after the end.
+    pub fn push_range(&mut self, start: u8, end: u8) {
+        *self = self.with_range(start, end);
+    }
+
+    /// Find the index of the first character within the ranges.
+    ///
+    /// The `PCMPESTRI` instruction is used when the running CPU
+    /// supports SSE 4.2; otherwise each byte is compared in turn.
+    #[inline]
+    pub fn find<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { sse42::find::<sse42::Ranges>(self.needle, self.count, haystack) },
+            _ => fallback::find_in_ranges(self.needle, self.count, haystack),
+        }
+    }
+
+    /// Find the index of the last character within the ranges.
+    #[inline]
+    pub fn rfind<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { sse42::rfind::<sse42::Ranges>(self.needle, self.count, haystack) },
+            _ => fallback::rfind_in_ranges(self.needle, self.count, haystack),
+        }
+    }
+
+    /// Find the index of the first character outside all of the ranges.
+    #[inline]
+    pub fn find_not<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { sse42::find::<sse42::NotRanges>(self.needle, self.count, haystack) },
+            _ => fallback::find_not_in_ranges(self.needle, self.count, haystack),
+        }
+    }
+
+    /// Find the index of the last character outside all of the ranges.
+    #[inline]
+    pub fn rfind_not<H>(self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { sse42::rfind::<sse42::NotRanges>(self.needle, self.count, haystack) },
+            _ => fallback::rfind_not_in_ranges(self.needle, self.count, haystack),
+        }
+    }
+
+    /// An iterator over the ranges of each maximal run of consecutive
+    /// characters within the ranges.
+    #[inline]
+    pub fn runs<H>(self, haystack: &H) -> Runs<'_, AsciiRanges>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        Runs::new(self, haystack.as_ref())
+    }
+}
+
+impl Default for AsciiRanges {
+    fn default() -> AsciiRanges {
+        AsciiRanges::new()
+    }
+}
+
+/// Parses the syntax of a regular expression's character class, as
+/// for `AsciiChars`. Each maximal run of characters in the class
+/// becomes a range, so the class may list any number of characters.
+///
+/// ```
+/// use jetscii::AsciiRanges;
+/// let printable: AsciiRanges = r"[^\x00-\x1f\x7f]".parse().unwrap();
+/// assert_eq!(printable.find("\t\tabc"), Some(2));
+/// assert_eq!(printable.to_string(), r"[ -~]");
+/// ```
+impl FromStr for AsciiRanges {
+    type Err = ParseSetError;
+
+    fn from_str(s: &str) -> Result<AsciiRanges, ParseSetError> {
+        let class = Class::parse(s, 128)?;
+
+        let mut ranges = AsciiRanges::new();
+        for (start, end) in class.ranges() {
+            if end >= 128 {
+                return Err(AsciiCharsError::NonAscii { byte: cmp::max(start, 128) }.into());
+            }
+            if ranges.count >= 16 {
+                return Err(AsciiCharsError::CapacityExceeded { max: 8 }.into());
+            }
+            ranges.push_range(start, end);
+        }
+        Ok(ranges)
+    }
+}
+
+/// Prints the characters within the ranges in the syntax accepted by
+/// `from_str`.
+impl fmt::Display for AsciiRanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Class::from_fn(|b| fallback::in_ranges(self.needle, self.count, b)).fmt(f)
+    }
+}
+
+impl fmt::Debug for AsciiRanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The first range pushed is the most significant
+        write!(f, "AsciiRanges([")?;
+        for i in (0..self.count / 2).rev() {
+            let start = (self.needle >> (i * 16)) as u8;
+            let end = (self.needle >> (i * 16 + 8)) as u8;
+            let separator = if i == 0 { "" } else { ", " };
+            write!(f, "b'{}'..=b'{}'{}", ascii::escape_default(start), ascii::escape_default(end), separator)?;
+        }
+        write!(f, "])")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::AsciiRanges;
+    use error::{AsciiCharsError,ParseSetError};
+    #[cfg(target_arch = "x86_64")]
+    use fallback;
+
+    /// Builds up to 8 valid ranges from arbitrary pairs of bytes.
+    fn ranges(pairs: &[(u8, u8)]) -> (AsciiRanges, Vec<(u8, u8)>) {
+        let pairs: Vec<_> = pairs.iter().take(8).map(|&(a, b)| {
+            let (a, b) = (a & 0x7F, b & 0x7F);
+            (a.min(b), a.max(b))
+        }).collect();
+
+        let mut ranges = AsciiRanges::new();
+        for &(start, end) in &pairs {
+            ranges.push_range(start, end);
+        }
+        (ranges, pairs)
+    }
+
+    #[test]
+    fn works_as_position_does() {
+        fn prop(haystack: Vec<u8>, pairs: Vec<(u8, u8)>) -> bool {
+            let (ranges, pairs) = ranges(&pairs);
+            let in_ranges = |b: &u8| pairs.iter().any(|&(start, end)| start <= *b && *b <= end);
+
+            let outside = |b: &u8| !in_ranges(b);
+
+            ranges.find(&haystack) == haystack.iter().position(in_ranges) &&
+                ranges.rfind(&haystack) == haystack.iter().rposition(in_ranges) &&
+                ranges.find_not(&haystack) == haystack.iter().position(outside) &&
+                ranges.rfind_not(&haystack) == haystack.iter().rposition(outside)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<(u8, u8)>) -> bool);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn sse42_agrees_with_fallback() {
+        use sse42;
+
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        fn prop(haystack: Vec<u8>, pairs: Vec<(u8, u8)>) -> bool {
+            let (AsciiRanges { needle, count }, _) = ranges(&pairs);
+            let simd = unsafe { sse42::find::<sse42::Ranges>(needle, count, &haystack) };
+            let simd_rev = unsafe { sse42::rfind::<sse42::Ranges>(needle, count, &haystack) };
+            let simd_not = unsafe { sse42::find::<sse42::NotRanges>(needle, count, &haystack) };
+            let simd_rev_not = unsafe { sse42::rfind::<sse42::NotRanges>(needle, count, &haystack) };
+            simd == fallback::find_in_ranges(needle, count, &haystack) &&
+                simd_rev == fallback::rfind_in_ranges(needle, count, &haystack) &&
+                simd_not == fallback::find_not_in_ranges(needle, count, &haystack) &&
+                simd_rev_not == fallback::rfind_not_in_ranges(needle, count, &haystack)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<(u8, u8)>) -> bool);
+    }
+
+    #[test]
+    fn finds_characters_at_either_end_of_a_range() {
+        let mut digits = AsciiRanges::new();
+        digits.push_range(b'0', b'9');
+
+        assert_eq!(Some(3), digits.find("abc0"));
+        assert_eq!(Some(3), digits.find("abc9"));
+        assert_eq!(None, digits.find("abc/:"));
+        assert_eq!(Some(0), digits.rfind("5abcdefghijklmnopqrstuvwxyz"));
+    }
+
+    #[test]
+    fn runs_of_digits_are_found() {
+        let mut digits = AsciiRanges::new();
+        digits.push_range(b'0', b'9');
+
+        let runs: Vec<_> = digits.runs("a1b22c333").collect();
+        assert_eq!(runs, [1..2, 3..5, 6..9]);
+        let mut runs = digits.runs("0123456789012345678901234567890123456789");
+        assert_eq!(runs.next(), Some(0..40));
+        assert_eq!(runs.next(), None);
+    }
+
+    #[test]
+    fn parses_each_run_as_a_range() {
+        let word: AsciiRanges = r"[a-z0-9_A-Z]".parse().unwrap();
+        assert_eq!(word.find("  foo_bar"), Some(2));
+        assert_eq!(word.find_not("foo_bar  "), Some(7));
+        assert_eq!(word.to_string(), "[0-9A-Z_a-z]");
+
+        let separated: Result<AsciiRanges, _> = "acegikmoqs".parse();
+        assert_eq!(separated.err(), Some(ParseSetError::Set(AsciiCharsError::CapacityExceeded { max: 8 })));
+        let non_ascii: Result<AsciiRanges, _> = r"\x70-\x90".parse();
+        assert_eq!(non_ascii.err(), Some(ParseSetError::Set(AsciiCharsError::NonAscii { byte: 0x80 })));
+    }
+
+    #[test]
+    fn debug_shows_the_ranges() {
+        const CONTROL: AsciiRanges = AsciiRanges::new().with_range(0x00, 0x1f).with_range(0x7f, 0x7f);
+        assert_eq!(format!("{:?}", CONTROL), r"AsciiRanges([b'\x00'..=b'\x1f', b'\x7f'..=b'\x7f'])");
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_the_range_is_backwards() {
+        AsciiRanges::new().push_range(b'z', b'a');
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_when_adding_a_ninth_range() {
+        let mut ranges = AsciiRanges::new();
+        for b in 0..9 {
+            ranges.push_range(b, b);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_pattern() {
+        fn prop(s: String, pairs: Vec<(u8, u8)>) -> bool {
+            let (ranges, pairs) = ranges(&pairs);
+            let in_ranges = |c: char| pairs.iter().any(|&(start, end)| start as char <= c && c <= end as char);
+
+            s.find(ranges) == s.find(in_ranges) &&
+                s.split(ranges).eq(s.split(in_ranges)) &&
+                s.rsplit(ranges).eq(s.rsplit(in_ranges)) &&
+                s.trim_matches(ranges) == s.trim_matches(in_ranges)
+        }
+        quickcheck(prop as fn(String, Vec<(u8, u8)>) -> bool);
+    }
+}
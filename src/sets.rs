@@ -0,0 +1,136 @@
+//! Ready-made sets of commonly searched-for characters.
+//!
+//! Each set is a constant of the smallest type that can hold it, so
+//! it can be searched with directly or used as a `Pattern` with the
+//! `unstable` feature, without writing a fallback.
+//!
+//! ```
+//! use jetscii::sets;
+//! let line = "name,\"quoted, value\"\r\n";
+//! assert_eq!(sets::CSV.find(line), Some(4));
+//! assert_eq!(sets::WHITESPACE.rfind(line), Some(21));
+//! ```
+
+use {AsciiChars,AsciiRanges,ByteSet};
+
+/// ASCII whitespace, as defined by `u8::is_ascii_whitespace`: space,
+/// tab, line feed, form feed and carriage return.
+pub const WHITESPACE: AsciiChars = AsciiChars::from_bytes(b" \t\n\x0c\r");
+
+/// The characters that must be escaped in XML text. A `>` only needs
+/// escaping when it ends `]]>`, but is commonly escaped everywhere.
+pub const XML_TEXT: AsciiChars = AsciiChars::from_bytes(b"<>&");
+
+/// The characters that must be escaped in an XML attribute value
+/// quoted with either kind of quote.
+pub const XML_ATTRIBUTE: AsciiChars = AsciiChars::from_bytes(b"<>&'\"");
+
+/// The characters escaped when writing HTML text or quoted attribute
+/// values, which are the same as `XML_ATTRIBUTE`.
+pub const HTML: AsciiChars = XML_ATTRIBUTE;
+
+/// The characters that must be escaped in a JSON string: the quote,
+/// the backslash and the control characters.
+pub const JSON_STRING: AsciiRanges = AsciiRanges::new()
+    .with_range(0x00, 0x1f)
+    .with_range(b'"', b'"')
+    .with_range(b'\\', b'\\');
+
+/// The characters that require a CSV field to be quoted.
+pub const CSV: AsciiChars = AsciiChars::from_bytes(b",\"\r\n");
+
+/// The reserved characters of a URL, as listed by RFC 3986: the
+/// general delimiters and the sub-delimiters.
+pub const URL_RESERVED: ByteSet = ByteSet::from_bytes(b":/?#[]@!$&'()*+,;=");
+
+/// The characters that never need to be percent-encoded in a URL, as
+/// listed by RFC 3986. Use `find_not` to find those that do.
+pub const URL_UNRESERVED: AsciiRanges = AsciiRanges::new()
+    .with_range(b'a', b'z')
+    .with_range(b'A', b'Z')
+    .with_range(b'0', b'9')
+    .with_range(b'-', b'.')
+    .with_range(b'_', b'_')
+    .with_range(b'~', b'~');
+
+/// The characters that the POSIX shell treats specially, and so must
+/// be quoted to be used literally.
+pub const SHELL_META: ByteSet = ByteSet::from_bytes(b"|&;<>()$`\\\"' \t\n*?[#~=%");
+
+/// The characters that separate the components of a path on any
+/// common platform.
+pub const PATH_SEPARATORS: AsciiChars = AsciiChars::from_bytes(b"/\\");
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Checks each byte against the set by searching for it, so this
+    /// works for every kind of set.
+    fn assert_set<F, P>(find: F, expected: P)
+        where F: Fn(&[u8]) -> Option<usize>,
+              P: Fn(u8) -> bool
+    {
+        for b in 0..=255u8 {
+            assert_eq!(expected(b), find(&[b]) == Some(0), "byte {:#04x}", b);
+        }
+    }
+
+    #[test]
+    fn whitespace_is_ascii_whitespace() {
+        assert_set(|h| WHITESPACE.find(h), |b| b.is_ascii_whitespace());
+    }
+
+    #[test]
+    fn xml_sets_hold_the_specials() {
+        assert_set(|h| XML_TEXT.find(h), |b| b"<>&".contains(&b));
+        assert_set(|h| XML_ATTRIBUTE.find(h), |b| b"<>&'\"".contains(&b));
+        assert_set(|h| HTML.find(h), |b| b"<>&'\"".contains(&b));
+    }
+
+    #[test]
+    fn json_string_holds_quotes_backslashes_and_controls() {
+        assert_set(|h| JSON_STRING.find(h), |b| b == b'"' || b == b'\\' || b < 0x20);
+    }
+
+    #[test]
+    fn csv_holds_separators_quotes_and_newlines() {
+        assert_set(|h| CSV.find(h), |b| b",\"\r\n".contains(&b));
+    }
+
+    #[test]
+    fn url_sets_are_disjoint() {
+        let unreserved = |b: u8| b.is_ascii_alphanumeric() || b"-._~".contains(&b);
+        assert_set(|h| URL_UNRESERVED.find(h), unreserved);
+        assert_set(|h| URL_RESERVED.find(h), |b| b":/?#[]@!$&'()*+,;=".contains(&b));
+        assert!((0..=255).all(|b| !(unreserved(b) && URL_RESERVED.contains(b))));
+    }
+
+    #[test]
+    fn shell_meta_holds_what_must_be_quoted() {
+        assert_set(|h| SHELL_META.find(h), |b| b"|&;<>()$`\\\"' \t\n*?[#~=%".contains(&b));
+        assert_eq!(SHELL_META.find("rm -rf ./build"), Some(2));
+    }
+
+    #[test]
+    fn path_separators_hold_both_slashes() {
+        assert_set(|h| PATH_SEPARATORS.find(h), |b| b == b'/' || b == b'\\');
+        assert_eq!(PATH_SEPARATORS.rfind(r"C:\Users\me/file.txt"), Some(11));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn sets_work_as_patterns() {
+        let words: Vec<_> = " a\tb\r\nc ".split(WHITESPACE).filter(|s| !s.is_empty()).collect();
+        assert_eq!(words, ["a", "b", "c"]);
+        assert_eq!("a < b".find(XML_TEXT), Some(2));
+        assert_eq!("x=\"1\"".find(XML_ATTRIBUTE), Some(2));
+        assert_eq!("<p>".replace(HTML, "_"), "_p_");
+        assert_eq!("caf\u{e9} \"au lait\"".find(JSON_STRING), Some(6));
+        assert_eq!("a,b,\"c\"".split(CSV).count(), 5);
+        assert_eq!("/path?q".find(URL_RESERVED), Some(0));
+        assert_eq!("ab%20".trim_start_matches(URL_UNRESERVED), "%20");
+        assert_eq!("echo $HOME".find(SHELL_META), Some(4));
+        assert_eq!("a/b\\c".rsplit(PATH_SEPARATORS).next(), Some("c"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Searching by comparing a block against each character in turn,
+//! using the portable `std::simd` types.
+//!
+//! This needs a nightly compiler, and is used on architectures that
+//! have no hand-written kernel. Unlike the x86-64 kernels, only the
+//! bytes of the haystack are ever read; a partial block at either end
+//! is copied into a zeroed block first.
+
+use std::cmp;
+use std::simd::prelude::*;
+
+use dispatch::{Matches,Search};
+
+/// The number of bytes compared at once; one 128-bit register on most
+/// architectures.
+const WIDTH: usize = 16;
+
+type Block = Simd<u8, WIDTH>;
+
+/// The needle bytes, each repeated across a whole block as it is
+/// compared.
+///
+/// Storing every byte already repeated would take 256 bytes, which
+/// every `Finder` would then carry. Most architectures can load a byte
+/// repeated across a register with a single instruction, so this costs
+/// no more than loading a stored block.
+#[derive(Copy,Clone)]
+pub struct Chars {
+    bytes: [u8; 16],
+    count: usize,
+}
+
+impl Chars {
+    /// The first `count` bytes of `needle`.
+    #[inline]
+    pub fn new(needle: u128, count: u8) -> Chars {
+        Chars { bytes: needle.to_le_bytes(), count: count as usize }
+    }
+
+    #[inline]
+    pub fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        match search {
+            Search::Find => forward(self, haystack, false),
+            Search::Rfind => backward(self, haystack, false),
+            Search::FindNot => forward(self, haystack, true),
+            Search::RfindNot => backward(self, haystack, true),
+        }
+    }
+
+    /// Count the bytes of the haystack in the set.
+    #[inline]
+    pub fn count(&self, haystack: &[u8]) -> usize {
+        haystack.chunks(WIDTH).map(|chunk| self.mask(chunk, false).count_ones() as usize).sum()
+    }
+
+    /// The matches in the block that holds byte `idx` of the haystack,
+    /// counting blocks from its start.
+    #[inline]
+    pub fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        let start = idx & !(WIDTH - 1);
+        let end = cmp::min(start + WIDTH, haystack.len());
+        Matches { start, end, mask: self.mask(&haystack[start..end], false) }
+    }
+
+    /// Returns a mask where bit `i` is set if byte `i` of the chunk
+    /// matches, ignoring any bytes past the end of the chunk.
+    #[inline]
+    fn mask(&self, chunk: &[u8], invert: bool) -> u64 {
+        let block = Block::load_or_default(chunk);
+
+        let mut matches = Mask::splat(false);
+        for &byte in &self.bytes[..self.count] {
+            matches |= block.simd_eq(Block::splat(byte));
+        }
+        if invert {
+            matches = !matches;
+        }
+        matches.to_bitmask() & low_bits(chunk.len())
+    }
+}
+
+/// Count each of `bytes` in the haystack separately, adding each count
+/// to the tally in the same position.
+#[inline]
+pub fn count_each(bytes: &[u8], haystack: &[u8], tallies: &mut [usize]) {
+    for chunk in haystack.chunks(WIDTH) {
+        let block = Block::load_or_default(chunk);
+        let valid = low_bits(chunk.len());
+        for (&b, tally) in bytes.iter().zip(tallies.iter_mut()) {
+            *tally += (block.simd_eq(Block::splat(b)).to_bitmask() & valid).count_ones() as usize;
+        }
+    }
+}
+
+#[inline]
+fn low_bits(n: usize) -> u64 {
+    if n >= 64 { !0 } else { (1 << n) - 1 }
+}
+
+#[inline]
+fn forward(chars: &Chars, haystack: &[u8], invert: bool) -> Option<usize> {
+    haystack.chunks(WIDTH).enumerate().find_map(|(i, chunk)| {
+        match chars.mask(chunk, invert) {
+            0 => None,
+            mask => Some(i * WIDTH + mask.trailing_zeros() as usize),
+        }
+    })
+}
+
+#[inline]
+fn backward(chars: &Chars, haystack: &[u8], invert: bool) -> Option<usize> {
+    // Chunks from the end leave any partial chunk at the start
+    haystack.rchunks(WIDTH).enumerate().find_map(|(i, chunk)| {
+        match chars.mask(chunk, invert) {
+            0 => None,
+            mask => {
+                let start = haystack.len().saturating_sub((i + 1) * WIDTH);
+                Some(start + 63 - mask.leading_zeros() as usize)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::{Chars,WIDTH};
+    use dispatch::Search;
+    use dispatch::test::{by_blocks,positions,SEARCHES};
+    use fallback;
+    use AsciiChars;
+
+    #[test]
+    fn agrees_with_fallback() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>) -> bool {
+            let chars: AsciiChars = bytes.iter().take(16).map(|&b| b & 0x7F).collect();
+            let (needle, count) = (chars.needle, chars.count);
+
+            SEARCHES.iter().all(|&search| {
+                Chars::new(needle, count).search(search, &haystack) == fallback::chars(needle, count, search, &haystack)
+            }) && Chars::new(needle, count).count(&haystack) == fallback::count(needle, count, &haystack) && {
+                let bytes: Vec<u8> = chars.iter().collect();
+                let (mut simd, mut expected) = (vec![0; bytes.len()], vec![0; bytes.len()]);
+                super::count_each(&bytes, &haystack, &mut simd);
+                fallback::count_each(&bytes, &haystack, &mut expected);
+                simd == expected
+            }
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn finds_matches_in_partial_blocks() {
+        let chars = AsciiChars::from_bytes(b"<>&");
+        for len in 1..50 {
+            for pos in 0..len {
+                let mut haystack = vec![b'a'; len];
+                haystack[pos] = b'&';
+                assert_eq!(Chars::new(chars.needle, chars.count).search(Search::Find, &haystack), Some(pos));
+                assert_eq!(Chars::new(chars.needle, chars.count).search(Search::Rfind, &haystack), Some(pos));
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_hold_every_match() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>) -> bool {
+            let chars: AsciiChars = bytes.iter().take(16).map(|&b| b & 0x7F).collect();
+            let simd = Chars::new(chars.needle, chars.count);
+            by_blocks(&haystack, WIDTH, |idx| simd.block(&haystack, idx)) == positions(&haystack, |x| chars.contains(x))
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+}
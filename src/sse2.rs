@@ -0,0 +1,238 @@
+//! Searching by comparing a 16-byte block against each character in
+//! turn, using SSE2.
+//!
+//! Every x86-64 CPU supports SSE2, so this is used when SSE 4.2 is not
+//! available, such as on older CPUs or in virtual machines that hide
+//! it. A single character or a contiguous range of characters is
+//! faster to search this way than with `PCMPESTRI`, so those sets use
+//! it even when SSE 4.2 is available.
+
+use std::arch::x86_64::{
+    __m128i, _mm_cmpeq_epi8, _mm_load_si128, _mm_movemask_epi8, _mm_or_si128, _mm_set1_epi8,
+    _mm_setzero_si128, _mm_sub_epi8, _mm_subs_epu8,
+};
+
+use dispatch::{Matches,Search};
+use mask::{self,Matcher};
+
+/// Each of the needle bytes, repeated across a whole block.
+#[derive(Copy,Clone)]
+pub struct Chars {
+    bytes: [__m128i; 16],
+    count: usize,
+}
+
+impl Chars {
+    #[inline(always)]
+    unsafe fn new(needle: u128, count: u8) -> Chars {
+        let mut bytes = [_mm_setzero_si128(); 16];
+        for (i, b) in bytes.iter_mut().enumerate().take(count as usize) {
+            *b = _mm_set1_epi8((needle >> (i * 8)) as u8 as i8);
+        }
+        Chars { bytes, count: count as usize }
+    }
+}
+
+impl Matcher for Chars {
+    const WIDTH: usize = 16;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm_load_si128(ptr as *const __m128i);
+
+        let mut matches = _mm_setzero_si128();
+        for &byte in &self.bytes[..self.count] {
+            matches = _mm_or_si128(matches, _mm_cmpeq_epi8(block, byte));
+        }
+        _mm_movemask_epi8(matches) as u16 as u64
+    }
+}
+
+/// A single byte, repeated across a whole block.
+#[derive(Copy,Clone)]
+pub struct Byte(__m128i);
+
+impl Byte {
+    #[inline(always)]
+    unsafe fn new(byte: u8) -> Byte {
+        Byte(_mm_set1_epi8(byte as i8))
+    }
+}
+
+impl Matcher for Byte {
+    const WIDTH: usize = 16;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm_load_si128(ptr as *const __m128i);
+        _mm_movemask_epi8(_mm_cmpeq_epi8(block, self.0)) as u16 as u64
+    }
+}
+
+/// An inclusive range of bytes, matched as the AVX2 kernel does.
+#[derive(Copy,Clone)]
+pub struct Range {
+    start: __m128i,
+    width: __m128i,
+}
+
+impl Range {
+    /// The bytes from `start` to `end` inclusive; `start` must not be
+    /// after `end`.
+    #[inline(always)]
+    unsafe fn new(start: u8, end: u8) -> Range {
+        Range {
+            start: _mm_set1_epi8(start as i8),
+            width: _mm_set1_epi8((end - start) as i8),
+        }
+    }
+}
+
+impl Matcher for Range {
+    const WIDTH: usize = 16;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm_load_si128(ptr as *const __m128i);
+        let offset = _mm_sub_epi8(block, self.start);
+        let beyond = _mm_subs_epu8(offset, self.width);
+        _mm_movemask_epi8(_mm_cmpeq_epi8(beyond, _mm_setzero_si128())) as u16 as u64
+    }
+}
+
+/// A set prepared for searching, so that the bytes are only repeated
+/// across a block once.
+#[derive(Copy,Clone)]
+pub enum Compiled {
+    Byte(Byte),
+    Range(Range),
+    /// Repeating each of up to 16 bytes would take 256 bytes of
+    /// registers, which every `Finder` would then carry. It only takes
+    /// a few instructions, so it is done at the start of each search.
+    Chars { needle: u128, count: u8 },
+}
+
+impl Compiled {
+    /// ### Safety
+    ///
+    /// The CPU must support SSE2, which every x86-64 CPU does.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn byte(byte: u8) -> Compiled {
+        Compiled::Byte(Byte::new(byte))
+    }
+
+    /// The bytes from `start` to `end` inclusive.
+    ///
+    /// ### Safety
+    ///
+    /// As for `byte`, and `start` must not be after `end`.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn range(start: u8, end: u8) -> Compiled {
+        Compiled::Range(Range::new(start, end))
+    }
+
+    /// The first `count` bytes of `needle`.
+    ///
+    /// ### Safety
+    ///
+    /// As for `byte`.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn chars(needle: u128, count: u8) -> Compiled {
+        Compiled::Chars { needle, count }
+    }
+
+    /// ### Safety
+    ///
+    /// As for `byte`.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        match *self {
+            Compiled::Byte(ref byte) => mask::search(byte, search, haystack),
+            Compiled::Range(ref range) => mask::search(range, search, haystack),
+            Compiled::Chars { needle, count } => mask::search(&Chars::new(needle, count), search, haystack),
+        }
+    }
+
+    /// Count the bytes of the haystack in the set.
+    ///
+    /// ### Safety
+    ///
+    /// As for `byte`.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn count(&self, haystack: &[u8]) -> usize {
+        match *self {
+            Compiled::Byte(ref byte) => mask::count(byte, haystack),
+            Compiled::Range(ref range) => mask::count(range, haystack),
+            Compiled::Chars { needle, count } => mask::count(&Chars::new(needle, count), haystack),
+        }
+    }
+
+    /// The matches in the aligned block that holds byte `idx` of the
+    /// haystack.
+    ///
+    /// ### Safety
+    ///
+    /// As for `byte`, and `idx` must be within the haystack.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        match *self {
+            Compiled::Byte(ref byte) => mask::block(byte, haystack, idx),
+            Compiled::Range(ref range) => mask::block(range, haystack, idx),
+            Compiled::Chars { needle, count } => mask::block(&Chars::new(needle, count), haystack, idx),
+        }
+    }
+}
+
+/// Count each of up to 16 `bytes` in the haystack separately, adding
+/// each count to the tally in the same position.
+///
+/// ### Safety
+///
+/// The CPU must support SSE2, which every x86-64 CPU does.
+#[target_feature(enable = "sse2")]
+pub unsafe fn count_each(bytes: &[u8], haystack: &[u8], tallies: &mut [usize]) {
+    let mut matchers = [Byte(_mm_setzero_si128()); 16];
+    for (matcher, &b) in matchers.iter_mut().zip(bytes) {
+        *matcher = Byte::new(b);
+    }
+    mask::count_each(&matchers[..bytes.len()], haystack, tallies)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::{Byte,Chars,Compiled,Range};
+    use dispatch::Search;
+    use mask::test::check;
+    use AsciiChars;
+
+    #[test]
+    fn agrees_with_a_predicate() {
+        fn prop(haystack: Vec<u8>, bytes: Vec<u8>, a: u8, b: u8) -> bool {
+            let chars: AsciiChars = bytes.iter().take(16).map(|&b| b & 0x7F).collect();
+            let (start, end) = (a.min(b), a.max(b));
+            unsafe {
+                check(&Chars::new(chars.needle, chars.count), &haystack, |x| chars.contains(x)) &&
+                    check(&Byte::new(a), &haystack, |x| x == a) &&
+                    check(&Range::new(start, end), &haystack, |x| start <= x && x <= end)
+            }
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>, u8, u8) -> bool);
+    }
+
+    #[test]
+    fn finds_each_of_sixteen_characters() {
+        let delimiters = b"<>&'\"=/ \t\r\n?#[]@";
+        let chars = AsciiChars::from_bytes(delimiters);
+        for &b in delimiters {
+            let haystack = format!("0123456789abcdefghij{}", b as char);
+            unsafe {
+                let compiled = Compiled::chars(chars.needle, chars.count);
+                assert_eq!(Some(20), compiled.search(Search::Find, haystack.as_bytes()));
+                assert_eq!(Some(20), compiled.search(Search::Rfind, haystack.as_bytes()));
+            }
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Searching using the SSE 4.2 `PCMPESTRI` and `PCMPESTRM`
+//! instructions.
+
+use std::arch::x86_64::{
+    __m128i, _mm_cmpestri, _mm_cmpestrm, _mm_cvtsi128_si32, _mm_load_si128, _mm_loadu_si128,
+    _mm_set_epi64x, _SIDD_BIT_MASK, _SIDD_CMP_EQUAL_ANY, _SIDD_CMP_EQUAL_ORDERED,
+    _SIDD_CMP_RANGES, _SIDD_MASKED_NEGATIVE_POLARITY, _SIDD_MOST_SIGNIFICANT, _SIDD_UBYTE_OPS,
+};
+use std::cmp;
+
+use dispatch::{Matches,Search};
+use mask::{self,Matcher};
+
+/// How the instructions compare the haystack against the needle.
+///
+/// The control byte has to be a constant, so each mode is a separate
+/// type.
+pub trait Mode {
+    /// The index of the first matching byte, or 16.
+    unsafe fn first(needle: __m128i, count: i32, block: __m128i, len: i32) -> usize;
+    /// The index of the last matching byte, or 16.
+    unsafe fn last(needle: __m128i, count: i32, block: __m128i, len: i32) -> usize;
+    /// A bitmask of all the matching bytes.
+    unsafe fn mask(needle: __m128i, count: i32, block: __m128i, len: i32) -> u32;
+}
+
+macro_rules! mode {
+    ($(#[$attr:meta])* $name:ident = $control:expr) => {
+        $(#[$attr])*
+        pub struct $name;
+
+        impl Mode for $name {
+            #[inline(always)]
+            unsafe fn first(needle: __m128i, count: i32, block: __m128i, len: i32) -> usize {
+                _mm_cmpestri::<{ $control }>(needle, count, block, len) as usize
+            }
+
+            #[inline(always)]
+            unsafe fn last(needle: __m128i, count: i32, block: __m128i, len: i32) -> usize {
+                _mm_cmpestri::<{ $control | _SIDD_MOST_SIGNIFICANT }>(needle, count, block, len) as usize
+            }
+
+            #[inline(always)]
+            unsafe fn mask(needle: __m128i, count: i32, block: __m128i, len: i32) -> u32 {
+                let mask = _mm_cmpestrm::<{ $control | _SIDD_BIT_MASK }>(needle, count, block, len);
+                _mm_cvtsi128_si32(mask) as u32
+            }
+        }
+    };
+}
+
+mode! {
+    /// Compare unsigned bytes, matching if the byte equals any of the
+    /// needle bytes.
+    EqualAny = _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_ANY
+}
+
+mode! {
+    /// Compare unsigned bytes, matching if the byte lies within any of
+    /// the inclusive ranges formed by pairs of needle bytes.
+    Ranges = _SIDD_UBYTE_OPS | _SIDD_CMP_RANGES
+}
+
+// Negating the result of the comparison finds the bytes that are
+// *not* in the set. The masked variant leaves the bytes past the
+// length of the haystack unmatched.
+
+mode! {
+    /// Compare unsigned bytes, matching if the byte equals none of
+    /// the needle bytes.
+    NotEqualAny = _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_ANY | _SIDD_MASKED_NEGATIVE_POLARITY
+}
+
+mode! {
+    /// Compare unsigned bytes, matching if the byte lies outside all
+    /// of the ranges.
+    NotRanges = _SIDD_UBYTE_OPS | _SIDD_CMP_RANGES | _SIDD_MASKED_NEGATIVE_POLARITY
+}
+
+mode! {
+    /// Compare unsigned bytes, matching where the whole needle starts.
+    /// A needle that runs off the end of the block is matched if the
+    /// bytes that are present agree.
+    EqualOrdered = _SIDD_UBYTE_OPS | _SIDD_CMP_EQUAL_ORDERED
+}
+
+enum InitialMatch {
+    Complete(Option<usize>),
+    Incomplete(usize),
+}
+
+/// The first `count` bytes of a needle, loaded into a register as the
+/// instructions take them, so that it can be searched for many times.
+#[derive(Copy,Clone)]
+pub struct Needle {
+    needle: __m128i,
+    count: i32,
+}
+
+impl Needle {
+    /// ### Safety
+    ///
+    /// The CPU must support SSE2, which every x86-64 CPU does.
+    #[inline(always)]
+    pub unsafe fn new(needle: u128, count: u8) -> Needle {
+        Needle {
+            needle: _mm_set_epi64x((needle >> 64) as i64, needle as i64),
+            count: count as i32,
+        }
+    }
+
+    /// Search `haystack` for the bytes of the needle, or for the bytes
+    /// that are none of them.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support SSE 4.2.
+    #[target_feature(enable = "sse4.2")]
+    pub unsafe fn search(&self, search: Search, haystack: &[u8]) -> Option<usize> {
+        match search {
+            Search::Find => find_needle::<EqualAny>(self, haystack),
+            Search::Rfind => rfind_needle::<EqualAny>(self, haystack),
+            Search::FindNot => find_needle::<NotEqualAny>(self, haystack),
+            Search::RfindNot => rfind_needle::<NotEqualAny>(self, haystack),
+        }
+    }
+
+    /// Count the bytes of `haystack` that are one of the bytes of the
+    /// needle.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support SSE 4.2.
+    #[target_feature(enable = "sse4.2")]
+    pub unsafe fn count(&self, haystack: &[u8]) -> usize {
+        mask::count(self, haystack)
+    }
+
+    /// The matches of the bytes of the needle in the aligned block
+    /// that holds byte `idx` of the haystack.
+    ///
+    /// ### Safety
+    ///
+    /// The CPU must support SSE 4.2, and `idx` must be within the
+    /// haystack.
+    #[target_feature(enable = "sse4.2")]
+    pub unsafe fn block(&self, haystack: &[u8], idx: usize) -> Matches {
+        mask::block(self, haystack, idx)
+    }
+}
+
+/// Matches a whole aligned block against the bytes of the needle with
+/// `PCMPESTRM`, as `count` and `block` do.
+impl Matcher for Needle {
+    const WIDTH: usize = 16;
+
+    #[inline(always)]
+    unsafe fn mask(&self, ptr: *const u8) -> u64 {
+        let block = _mm_load_si128(ptr as *const __m128i);
+        EqualAny::mask(self.needle, self.count, block, 16) as u64
+    }
+}
+
+/// Find the index of the first byte in `haystack` that matches the
+/// first `count` bytes of `needle`.
+///
+/// ### Safety
+///
+/// The CPU must support SSE 4.2.
+#[inline]
+#[target_feature(enable = "sse4.2")]
+pub unsafe fn find<M: Mode>(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    find_needle::<M>(&Needle::new(needle, count), haystack)
+}
+
+#[inline]
+#[target_feature(enable = "sse4.2")]
+unsafe fn find_needle<M: Mode>(needle: &Needle, haystack: &[u8]) -> Option<usize> {
+    let mut len = haystack.len();
+
+    if len == 0 { return None }
+
+    let Needle { needle, count } = *needle;
+
+    // The PCMPxSTRx instructions always read 16 bytes worth of
+    // data. To avoid walking off the end of a page (and
+    // potentially into a protected area), we read in 16-byte
+    // chunks aligned to the *end* of the string. The instructions
+    // handle truly unaligned access just fine; the trick lies in
+    // searching the leftover bytes at the beginning of the
+    // string.
+
+    let true_ptr = haystack.as_ptr();
+
+    // Find where the string really starts
+    let initial_offset = true_ptr as usize & 0xF;
+    let mut offset = initial_offset;
+
+    // Start at the 16-byte-aligned block *before* the string data
+    // starts
+    let ptr = true_ptr.wrapping_sub(initial_offset);
+
+    // If the string is magically aligned, skip this extra work
+    if offset != 0 {
+        match search_initial_unaligned_string::<M>(needle, count, ptr, offset, len) {
+            InitialMatch::Complete(result) => return result,
+            InitialMatch::Incomplete(length_of_leading_str) => {
+                offset = 16;
+                len -= length_of_leading_str;
+            }
+        }
+    }
+
+    // Compare four blocks per iteration. The comparisons don't
+    // depend on each other, so they can overlap, and only one branch
+    // is needed: an index of 16 means no match, and no smaller index
+    // has that bit set, so it survives the AND only if all four
+    // blocks missed.
+    while len >= 64 {
+        let block = |i: usize| _mm_load_si128(ptr.wrapping_add(offset + i * 16) as *const __m128i);
+        let res0 = M::first(needle, count, block(0), 16);
+        let res1 = M::first(needle, count, block(1), 16);
+        let res2 = M::first(needle, count, block(2), 16);
+        let res3 = M::first(needle, count, block(3), 16);
+
+        if res0 & res1 & res2 & res3 & 16 == 0 {
+            let index = if res0 != 16 {
+                res0
+            } else if res1 != 16 {
+                16 + res1
+            } else if res2 != 16 {
+                32 + res2
+            } else {
+                48 + res3
+            };
+            return Some(index + offset - initial_offset);
+        }
+
+        offset += 64;
+        len -= 64;
+    }
+
+    while len != 0 {
+        let block = _mm_load_si128(ptr.wrapping_add(offset) as *const __m128i);
+        let block_len = cmp::min(len, 16) as i32;
+        let res = M::first(needle, count, block, block_len);
+
+        // We know if it matched if the carry flag is set, but the
+        // index is just as cheap to test.
+        if res == 16 {
+            offset += 16;
+            len = len.saturating_sub(16);
+        } else {
+            return Some(res + offset - initial_offset);
+        }
+    }
+
+    None
+}
+
+#[inline]
+#[target_feature(enable = "sse4.2")]
+unsafe fn search_initial_unaligned_string<M: Mode>(
+    needle: __m128i,
+    count: i32,
+    ptr: *const u8,
+    offset: usize,
+    len: usize,
+) -> InitialMatch {
+    // We use the PCMPESTRM instruction on the 16-byte-aligned
+    // block that contains the *start* of the string. This returns
+    // a mask of all the matching characters. We can can then
+    // ignore unrelated leading bits to find the index of the
+    // first related character (if any).
+
+    let block = _mm_load_si128(ptr as *const __m128i);
+    let matching_bytes = M::mask(needle, count, block, 16);
+
+    // Ignore matches that occurred before our string began
+    let matching_bytes = matching_bytes >> offset;
+
+    if matching_bytes != 0 {
+        // Matched somewhere in there, pull out the index
+        let index = matching_bytes.trailing_zeros() as usize;
+
+        if index >= len {
+            // We matched, but not within our own string
+            return InitialMatch::Complete(None);
+        } else {
+            return InitialMatch::Complete(Some(index));
+        }
+    }
+
+    let length_of_leading_str = 16 - offset;
+
+    if len < length_of_leading_str {
+        // We've searched the entire string
+        InitialMatch::Complete(None)
+    } else {
+        InitialMatch::Incomplete(length_of_leading_str)
+    }
+}
+
+/// Find the index of the last byte in `haystack` that matches the
+/// first `count` bytes of `needle`.
+///
+/// ### Safety
+///
+/// The CPU must support SSE 4.2.
+#[inline]
+#[target_feature(enable = "sse4.2")]
+pub unsafe fn rfind<M: Mode>(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    rfind_needle::<M>(&Needle::new(needle, count), haystack)
+}
+
+#[inline]
+#[target_feature(enable = "sse4.2")]
+unsafe fn rfind_needle<M: Mode>(needle: &Needle, haystack: &[u8]) -> Option<usize> {
+    let len = haystack.len();
+
+    if len == 0 { return None }
+
+    let Needle { needle, count } = *needle;
+
+    // As when searching forwards, we only read 16-byte blocks that
+    // are aligned, so we never cross into another page. Asking for
+    // the *most* significant index means that any bytes before the
+    // start of the string only matter when nothing in the string
+    // matched, so there's no need for a mask.
+
+    let true_ptr = haystack.as_ptr();
+    let initial_offset = true_ptr as usize & 0xF;
+    let ptr = true_ptr.wrapping_sub(initial_offset);
+
+    // Offsets are relative to the aligned pointer
+    let end = initial_offset + len;
+    let mut offset = (end - 1) & !0xF;
+
+    loop {
+        let block = _mm_load_si128(ptr.wrapping_add(offset) as *const __m128i);
+        // Bytes after the end of the string are excluded by the length
+        let block_len = cmp::min(end - offset, 16) as i32;
+        let res = M::last(needle, count, block, block_len);
+
+        if res != 16 {
+            let index = offset + res;
+
+            if index < initial_offset {
+                // We matched, but before our own string began
+                return None;
+            } else {
+                return Some(index - initial_offset);
+            }
+        }
+
+        if offset == 0 {
+            return None;
+        }
+        offset -= 16;
+    }
+}
+
+/// Reads up to 16 bytes of `haystack` starting at `offset`, returning
+/// the block and the number of bytes in it.
+///
+/// Unlike the single byte searches, a match may span two aligned
+/// blocks, so blocks are read from wherever a match could start. A
+/// short block at the end of the haystack is copied first so that we
+/// never read past the end.
+#[inline(always)]
+unsafe fn load(haystack: &[u8], offset: usize) -> (__m128i, usize) {
+    let rest = &haystack[offset..];
+
+    if rest.len() >= 16 {
+        (_mm_loadu_si128(rest.as_ptr() as *const __m128i), 16)
+    } else {
+        let mut block = [0u8; 16];
+        block[..rest.len()].copy_from_slice(rest);
+        (_mm_loadu_si128(block.as_ptr() as *const __m128i), rest.len())
+    }
+}
+
+/// Find the index of the first occurrence in `haystack` of the first
+/// `count` bytes of `needle`, stored least significant byte first.
+///
+/// ### Safety
+///
+/// The CPU must support SSE 4.2, and `count` must be between 1 and
+/// 16.
+#[inline]
+#[target_feature(enable = "sse4.2")]
+pub unsafe fn find_substring(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    let len = haystack.len();
+    let needle_len = count as usize;
+    let needle = _mm_set_epi64x((needle >> 64) as i64, needle as i64);
+    let count = count as i32;
+
+    let mut offset = 0;
+
+    while offset + needle_len <= len {
+        let (block, block_len) = load(haystack, offset);
+        let res = EqualOrdered::first(needle, count, block, block_len as i32);
+
+        if res == 16 {
+            // Not even the start of the needle is in this block
+            offset += 16;
+        } else if res + needle_len <= block_len {
+            return Some(offset + res);
+        } else if block_len < 16 {
+            // The needle runs off the end of the haystack
+            return None;
+        } else {
+            // The needle runs off the end of the block, so look
+            // again starting at the possible match. It fits in the
+            // next block, so this always makes progress.
+            offset += res;
+        }
+    }
+
+    None
+}
+
+/// Find the index of the last occurrence in `haystack` of the first
+/// `count` bytes of `needle`, stored least significant byte first.
+///
+/// ### Safety
+///
+/// The CPU must support SSE 4.2, and `count` must be between 1 and
+/// 16.
+#[inline]
+#[target_feature(enable = "sse4.2")]
+pub unsafe fn rfind_substring(needle: u128, count: u8, haystack: &[u8]) -> Option<usize> {
+    let len = haystack.len();
+    let needle_len = count as usize;
+
+    if len < needle_len { return None }
+
+    let needle = _mm_set_epi64x((needle >> 64) as i64, needle as i64);
+    let count = count as i32;
+
+    // The last index where a match could start
+    let mut last = len - needle_len;
+
+    loop {
+        // The block holds every match that starts between here and
+        // `last`.
+        let start = last.saturating_sub(16 - needle_len);
+        let (block, block_len) = load(haystack, start);
+        let mask = EqualOrdered::mask(needle, count, block, block_len as i32);
+
+        // Ignore the matches that run off the end of the block; they
+        // were covered by the previous block.
+        let mask = mask & ((1 << (last - start + 1)) - 1);
+
+        if mask != 0 {
+            return Some(start + 31 - mask.leading_zeros() as usize);
+        }
+
+        if start == 0 {
+            return None;
+        }
+        last = start - 1;
+    }
+}
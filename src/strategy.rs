@@ -0,0 +1,116 @@
+//! Choosing how to search for a set of characters from its shape.
+
+/// How `AsciiChars` searches for its characters, chosen from the shape
+/// of the set as it is built.
+///
+/// ```
+/// use jetscii::{AsciiChars,Strategy};
+/// assert_eq!(AsciiChars::from_bytes(b"\n").strategy(), Strategy::Byte(b'\n'));
+/// assert_eq!(AsciiChars::from_bytes(b"0123456789").strategy(), Strategy::Range { start: b'0', end: b'9' });
+/// assert_eq!(AsciiChars::from_bytes(b"<>&").strategy(), Strategy::Set);
+/// ```
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Hash)]
+pub enum Strategy {
+    /// The set is empty, so no bytes are examined; nothing is found,
+    /// and every byte is not in the set.
+    Empty,
+    /// A single character, compared against a whole block at once as
+    /// `memchr` does.
+    Byte(u8),
+    /// A contiguous inclusive range of characters. The start of the
+    /// range is subtracted from each byte of a block, leaving those
+    /// within the range as the smallest values.
+    Range { start: u8, end: u8 },
+    /// Any other set, searched with `PCMPESTRI` or by comparing
+    /// against each character in turn.
+    Set,
+}
+
+impl Strategy {
+    /// The strategy for the first `count` bytes of `needle`, which
+    /// must all be ASCII.
+    pub(crate) const fn of(needle: u128, count: u8) -> Strategy {
+        let mut members = 0u128;
+        let mut i = 0;
+        while i < count {
+            members |= 1 << (needle >> (i * 8)) as u8;
+            i += 1;
+        }
+
+        if members == 0 {
+            return Strategy::Empty;
+        }
+
+        let start = members.trailing_zeros() as u8;
+        let end = 127 - members.leading_zeros() as u8;
+        if start == end {
+            Strategy::Byte(start)
+        } else if ((members >> start) + 1).is_power_of_two() {
+            // Shifting a contiguous run of bits down to the bottom and
+            // adding one carries all the way to a single bit
+            Strategy::Range { start, end }
+        } else {
+            Strategy::Set
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::Strategy;
+    use AsciiChars;
+
+    fn strategy(bytes: &[u8]) -> Strategy {
+        AsciiChars::from_bytes(bytes).strategy()
+    }
+
+    #[test]
+    fn chooses_from_the_shape_of_the_set() {
+        assert_eq!(strategy(b""), Strategy::Empty);
+        assert_eq!(strategy(b"\0"), Strategy::Byte(0));
+        assert_eq!(strategy(b"\x7f"), Strategy::Byte(0x7f));
+        assert_eq!(strategy(b"aaa"), Strategy::Byte(b'a'));
+        assert_eq!(strategy(b"ba"), Strategy::Range { start: b'a', end: b'b' });
+        assert_eq!(strategy(b"fedcba"), Strategy::Range { start: b'a', end: b'f' });
+        assert_eq!(strategy(b"\x70\x71\x72\x73\x74\x75\x76\x77\x78\x79\x7a\x7b\x7c\x7d\x7e\x7f"),
+                   Strategy::Range { start: 0x70, end: 0x7f });
+        assert_eq!(strategy(b"ac"), Strategy::Set);
+        assert_eq!(strategy(b"abcz"), Strategy::Set);
+    }
+
+    #[test]
+    fn is_chosen_in_a_constant() {
+        const DIGITS: AsciiChars = AsciiChars::from_bytes(b"0123456789");
+        const _: () = assert!(matches!(DIGITS.strategy(), Strategy::Range { start: b'0', end: b'9' }));
+    }
+
+    #[test]
+    fn ranges_are_searched_as_position_does() {
+        fn prop(haystack: Vec<u8>, start: u8, len: u8) -> bool {
+            let start = start & 0x7F;
+            let end = start.saturating_add(len % 16).min(0x7F);
+            let chars: AsciiChars = (start..=end).collect();
+            let in_range = |b: &u8| start <= *b && *b <= end;
+
+            chars.find(&haystack) == haystack.iter().position(in_range) &&
+                chars.rfind(&haystack) == haystack.iter().rposition(in_range) &&
+                chars.find_not(&haystack) == haystack.iter().position(|b| !in_range(b)) &&
+                chars.rfind_not(&haystack) == haystack.iter().rposition(|b| !in_range(b))
+        }
+        quickcheck(prop as fn(Vec<u8>, u8, u8) -> bool);
+    }
+
+    #[test]
+    fn empty_sets_find_nothing() {
+        let chars = AsciiChars::new();
+        assert_eq!(chars.find("abc"), None);
+        assert_eq!(chars.rfind("abc"), None);
+        assert_eq!(chars.find_not("abc"), Some(0));
+        assert_eq!(chars.rfind_not("abc"), Some(2));
+        assert_eq!(chars.find_not(""), None);
+        assert_eq!(chars.rfind_not(""), None);
+    }
+}
@@ -0,0 +1,307 @@
+//! Searching for a sequence of bytes.
+
+use std::{ascii,fmt};
+#[cfg(target_arch = "x86_64")]
+use std::cmp;
+
+use dispatch;
+#[cfg(target_arch = "x86_64")]
+use dispatch::Kernel;
+use fallback;
+#[cfg(target_arch = "x86_64")]
+use sse42;
+
+/// Searches a string for a substring, such as the `-->` that closes
+/// an XML comment.
+///
+/// ```
+/// use jetscii::Substring;
+/// let end_of_comment = Substring::new("-->");
+/// assert_eq!(end_of_comment.find("<!-- a -- b -->"), Some(12));
+/// ```
+///
+/// Needles of up to 16 bytes are searched for with the "equal
+/// ordered" mode of the `PCMPESTRI` instruction. For longer needles,
+/// the instruction finds the places where the first 16 bytes occur
+/// and the remainder is compared separately.
+///
+/// As with `str::find`, an empty needle matches at the start of the
+/// haystack.
+#[derive(Copy,Clone)]
+pub struct Substring<'n> {
+    needle: &'n [u8],
+    /// The first 16 bytes of the needle, as `PCMPESTRI` takes them.
+    #[cfg(target_arch = "x86_64")]
+    prefix: u128,
+}
+
+impl<'n> Substring<'n> {
+    pub fn new<N>(needle: &'n N) -> Substring<'n>
+        where N: ?Sized + AsRef<[u8]>
+    {
+        let needle = needle.as_ref();
+
+        Substring {
+            needle,
+            #[cfg(target_arch = "x86_64")]
+            prefix: prefix(needle),
+        }
+    }
+
+    /// The bytes being searched for.
+    pub fn as_bytes(&self) -> &'n [u8] {
+        self.needle
+    }
+
+    /// Find the index of the first occurrence of the needle.
+    #[inline]
+    pub fn find<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        if self.needle.is_empty() { return Some(0) }
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { self.find_sse42(haystack) },
+            _ => fallback::find_substring(self.needle, haystack),
+        }
+    }
+
+    /// Find the index of the last occurrence of the needle.
+    #[inline]
+    pub fn rfind<H>(&self, haystack: &H) -> Option<usize>
+        where H: ?Sized + AsRef<[u8]>
+    {
+        let haystack = haystack.as_ref();
+
+        if self.needle.is_empty() { return Some(haystack.len()) }
+
+        match dispatch::kernel() {
+            #[cfg(target_arch = "x86_64")]
+            // Safety: the dispatcher has checked that the CPU supports SSE 4.2.
+            Kernel::Sse42 | Kernel::Avx2 => unsafe { self.rfind_sse42(haystack) },
+            _ => fallback::rfind_substring(self.needle, haystack),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn find_sse42(&self, haystack: &[u8]) -> Option<usize> {
+        let (prefix, rest) = self.needle.split_at(cmp::min(self.needle.len(), 16));
+
+        // Only look for the prefix where the rest of the needle fits
+        let end = haystack.len().checked_sub(rest.len())?;
+        let mut offset = 0;
+
+        while offset < end {
+            let idx = offset + sse42::find_substring(self.prefix, prefix.len() as u8, &haystack[offset..end])?;
+
+            if haystack[idx + prefix.len()..].starts_with(rest) {
+                return Some(idx);
+            }
+            offset = idx + 1;
+        }
+
+        None
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn rfind_sse42(&self, haystack: &[u8]) -> Option<usize> {
+        let (prefix, rest) = self.needle.split_at(cmp::min(self.needle.len(), 16));
+
+        let mut end = haystack.len().checked_sub(rest.len())?;
+
+        loop {
+            let idx = sse42::rfind_substring(self.prefix, prefix.len() as u8, &haystack[..end])?;
+
+            if haystack[idx + prefix.len()..].starts_with(rest) {
+                return Some(idx);
+            }
+            // Any earlier match has a prefix that ends at least one
+            // byte sooner
+            end = idx + prefix.len() - 1;
+        }
+    }
+}
+
+/// The first 16 bytes of `needle`, least significant byte first and
+/// padded with zeros.
+#[cfg(target_arch = "x86_64")]
+fn prefix(needle: &[u8]) -> u128 {
+    let mut prefix = [0; 16];
+    let prefix_len = cmp::min(needle.len(), 16);
+    prefix[..prefix_len].copy_from_slice(&needle[..prefix_len]);
+    u128::from_le_bytes(prefix)
+}
+
+impl<'n> fmt::Debug for Substring<'n> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Substring(b\"")?;
+        for &b in self.needle {
+            write!(f, "{}", ascii::escape_default(b))?;
+        }
+        write!(f, "\")")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate quickcheck;
+
+    use self::quickcheck::quickcheck;
+    use super::Substring;
+    #[cfg(target_arch = "x86_64")]
+    use fallback;
+
+    /// Squashes arbitrary bytes into a small alphabet, so that the
+    /// needle occurs (and nearly occurs) often.
+    fn squash(bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|&b| b'a' + b % 3).collect()
+    }
+
+    fn position(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        if needle.is_empty() { return Some(0) }
+        haystack.windows(needle.len()).position(|w| w == needle)
+    }
+
+    fn rposition(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        if needle.is_empty() { return Some(haystack.len()) }
+        haystack.windows(needle.len()).rposition(|w| w == needle)
+    }
+
+    #[test]
+    fn works_as_find_does() {
+        fn prop(haystack: String, needle: String) -> bool {
+            let substring = Substring::new(&needle);
+            substring.find(&haystack) == haystack.find(&*needle) &&
+                substring.rfind(&haystack) == haystack.rfind(&*needle)
+        }
+        quickcheck(prop as fn(String, String) -> bool);
+    }
+
+    #[test]
+    fn works_as_windows_do() {
+        fn prop(haystack: Vec<u8>, needle: Vec<u8>) -> bool {
+            let (haystack, needle) = (squash(&haystack), squash(&needle[..needle.len() % 24]));
+            let substring = Substring::new(&needle);
+            substring.find(&haystack) == position(&haystack, &needle) &&
+                substring.rfind(&haystack) == rposition(&haystack, &needle)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn sse42_agrees_with_fallback() {
+        use sse42;
+
+        if !is_x86_feature_detected!("sse4.2") { return }
+
+        fn prop(haystack: Vec<u8>, needle: Vec<u8>) -> bool {
+            let needle = &needle[..needle.len() % 17];
+            if needle.is_empty() { return true }
+
+            let (haystack, needle) = (squash(&haystack), squash(needle));
+            let Substring { prefix, .. } = Substring::new(&needle);
+            let count = needle.len() as u8;
+            let simd = unsafe { sse42::find_substring(prefix, count, &haystack) };
+            let simd_rev = unsafe { sse42::rfind_substring(prefix, count, &haystack) };
+            simd == fallback::find_substring(&needle, &haystack) &&
+                simd_rev == fallback::rfind_substring(&needle, &haystack)
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn finds_needles_spanning_blocks() {
+        let haystack = "0123456789abcdef0123456789ABCDEF-->0123456789abcdef";
+        for start in 0..haystack.len() {
+            for end in start + 1..haystack.len() {
+                let needle = &haystack[start..end];
+                let substring = Substring::new(needle);
+                assert_eq!(substring.find(haystack), haystack.find(needle), "{:?}", needle);
+                assert_eq!(substring.rfind(haystack), haystack.rfind(needle), "{:?}", needle);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_needles_longer_than_sixteen_bytes() {
+        let needle = "<![CDATA[ sixteen plus ]]>";
+        let haystack = format!("<![CDATA[ sixteen plus ]]<![CDATA[ sixteen plus ]]>{}", needle);
+        let substring = Substring::new(needle);
+        assert_eq!(substring.find(&haystack), Some(25));
+        assert_eq!(substring.rfind(&haystack), Some(51));
+        assert_eq!(substring.find(&haystack[26..]), Some(25));
+        assert_eq!(substring.rfind(&haystack[..76]), Some(25));
+    }
+
+    #[test]
+    fn empty_needle_matches_at_either_end() {
+        let substring = Substring::new("");
+        assert_eq!(substring.find("abc"), Some(0));
+        assert_eq!(substring.rfind("abc"), Some(3));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn empty_needle_searchers_stay_done() {
+        use std::str::pattern::{Pattern,ReverseSearcher,Searcher,SearchStep};
+
+        let mut searcher = Substring::new("").into_searcher("a");
+        assert_eq!(searcher.next(), SearchStep::Match(0, 0));
+        assert_eq!(searcher.next(), SearchStep::Reject(0, 1));
+        assert_eq!(searcher.next(), SearchStep::Match(1, 1));
+        assert_eq!(searcher.next(), SearchStep::Done);
+        assert_eq!(searcher.next(), SearchStep::Done);
+        assert_eq!(searcher.next(), SearchStep::Done);
+        assert_eq!(searcher.next_back(), SearchStep::Done);
+
+        let mut searcher = Substring::new("").into_searcher("");
+        assert_eq!(searcher.next_back(), SearchStep::Match(0, 0));
+        assert_eq!(searcher.next_back(), SearchStep::Done);
+        assert_eq!(searcher.next_back(), SearchStep::Done);
+        assert_eq!(searcher.next_back(), SearchStep::Done);
+    }
+
+    #[test]
+    fn debug_shows_the_needle() {
+        assert_eq!(format!("{:?}", Substring::new("-->\r\n")), r#"Substring(b"-->\r\n")"#);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn works_as_pattern() {
+        fn prop(haystack: Vec<u8>, needle: Vec<u8>) -> bool {
+            // Mixing in a multi-byte character means matches and
+            // rejections have to respect character boundaries.
+            let to_str = |bytes: &[u8]| -> String {
+                bytes.iter().map(|&b| if b % 4 == 3 { 'é' } else { (b'a' + b % 4) as char }).collect()
+            };
+            let haystack = to_str(&haystack);
+            let needle = to_str(&needle[..needle.len() % 20]);
+            let substring = Substring::new(&needle);
+
+            haystack.find(substring) == haystack.find(&*needle) &&
+                haystack.rfind(substring) == haystack.rfind(&*needle) &&
+                haystack.split(substring).eq(haystack.split(&*needle)) &&
+                haystack.rsplit(substring).eq(haystack.rsplit(&*needle)) &&
+                haystack.match_indices(substring).eq(haystack.match_indices(&*needle)) &&
+                haystack.rmatch_indices(substring).eq(haystack.rmatch_indices(&*needle))
+        }
+        quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn needle_that_is_not_utf8_is_never_a_pattern_match() {
+        let substring = Substring::new(&[0xA9]);
+        assert_eq!(substring.find("é"), Some(1));
+        assert_eq!("é".find(substring), None);
+        assert!("é".split(substring).eq(vec!["é"]));
+    }
+}